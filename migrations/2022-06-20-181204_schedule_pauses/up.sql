@@ -0,0 +1,6 @@
+-- Your SQL goes here
+CREATE TABLE schedule_pauses(
+    server TEXT NOT NULL PRIMARY KEY,
+    paused_by BIGINT NOT NULL,
+    paused_at TIMESTAMP NOT NULL
+)
\ No newline at end of file
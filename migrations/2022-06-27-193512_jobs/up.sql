@@ -0,0 +1,13 @@
+-- Your SQL goes here
+CREATE TABLE jobs(
+    id BLOB NOT NULL PRIMARY KEY,
+    server TEXT NOT NULL,
+    kind TEXT NOT NULL,
+    action TEXT,
+    step TEXT NOT NULL,
+    state TEXT NOT NULL,
+    channel BIGINT,
+    created_at TIMESTAMP NOT NULL,
+    updated_at TIMESTAMP NOT NULL,
+    user BIGINT
+)
\ No newline at end of file
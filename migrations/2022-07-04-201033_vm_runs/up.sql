@@ -0,0 +1,9 @@
+-- Your SQL goes here
+CREATE TABLE vm_runs(
+    id BLOB NOT NULL PRIMARY KEY,
+    server TEXT NOT NULL,
+    started_by BIGINT,
+    started_at TIMESTAMP NOT NULL,
+    stopped_by BIGINT,
+    stopped_at TIMESTAMP
+)
\ No newline at end of file
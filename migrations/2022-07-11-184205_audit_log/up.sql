@@ -0,0 +1,15 @@
+-- Your SQL goes here
+CREATE TABLE audit_log(
+    id BLOB NOT NULL PRIMARY KEY,
+    user BIGINT NOT NULL,
+    user_tag TEXT NOT NULL,
+    guild BIGINT,
+    channel BIGINT NOT NULL,
+    command TEXT NOT NULL,
+    arguments TEXT NOT NULL,
+    instance TEXT,
+    permitted BOOLEAN NOT NULL,
+    error TEXT,
+    duration_ms BIGINT NOT NULL,
+    created_at TIMESTAMP NOT NULL
+)
\ No newline at end of file
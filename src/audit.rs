@@ -0,0 +1,86 @@
+use crate::command::{Invocation, CMD_PREFIX};
+use crate::conf::ConfigKey;
+use crate::hook::target_instance;
+use crate::sql::audit::NewAuditEntry;
+use crate::sql::uuid::Uuid;
+use crate::sql::{Sql, SqlKey};
+use crate::SimpleResult;
+use chrono::Utc;
+use serenity::client::Context;
+use serenity::model::id::ChannelId;
+use serenity::utils::Colour;
+
+/// Commands announced in the audit channel
+const PRIVILEGED: &[&str] = &["start", "stop", "exec"];
+
+/// Saves the invocation and its outcome, privileged commands are announced in the audit channel.
+pub async fn record(ctx: &Context, inv: &Invocation<'_>, permitted: bool, error: Option<&str>) {
+    let duration_ms = (Utc::now() - inv.received_at).num_milliseconds().max(0);
+    let arguments = inv.args.join(" ");
+    let user_tag = inv.user.tag();
+    let instance = target_instance(inv);
+
+    let audit_channel = {
+        let data = ctx.data.read().await;
+        let config = data.get::<ConfigKey>().unwrap();
+        let sql = data.get::<SqlKey>().unwrap();
+
+        let entry = NewAuditEntry {
+            id: Uuid::random(),
+            user: inv.user.id.0 as i64,
+            user_tag: &user_tag,
+            guild: inv.guild_id.map(|g| g.0 as i64),
+            channel: inv.channel_id.0 as i64,
+            command: inv.name,
+            arguments: &arguments,
+            instance: instance.as_deref(),
+            permitted,
+            error,
+            duration_ms,
+            created_at: inv.received_at.naive_utc(),
+        };
+        tri!(save(sql, &entry), "Error saving audit log entry");
+
+        config.audit_channel
+    };
+
+    let channel = match audit_channel {
+        Some(channel) if PRIVILEGED.contains(&inv.name) => ChannelId(channel),
+        _ => return,
+    };
+
+    let (outcome, colour) = match (permitted, error) {
+        (_, Some(_)) => ("Failed", Colour::RED),
+        (false, None) => ("Denied", Colour::ORANGE),
+        (true, None) => ("Succeeded", Colour::DARK_GREEN),
+    };
+
+    let res = channel
+        .send_message(ctx, |m| {
+            m.embed(|e| {
+                e.title(format!("{}{} {}", CMD_PREFIX, inv.name, arguments).trim_end())
+                    .colour(colour)
+                    .field("User", &user_tag, true)
+                    .field("Outcome", outcome, true)
+                    .field(
+                        "Duration",
+                        format!("{:.1} s", duration_ms as f64 / 1000.0),
+                        true,
+                    )
+                    .timestamp(inv.received_at);
+
+                if let Some(why) = error {
+                    e.description(why);
+                }
+
+                e
+            })
+        })
+        .await;
+
+    tri!(res, "Error posting to the audit channel");
+}
+
+fn save(sql: &Sql, entry: &NewAuditEntry<'_>) -> SimpleResult<()> {
+    entry.insert(&mut sql.connection.get()?)
+}
@@ -1,21 +1,29 @@
 pub mod authentication;
+#[cfg(test)]
+pub mod fake;
 #[macro_use]
 pub mod management;
 
+use crate::azure::authentication::certificate::CertificateCredential;
+use crate::azure::authentication::client_secret::ClientSecretCredential;
+use crate::azure::authentication::federated_token::FederatedTokenCredential;
+use crate::azure::authentication::managed_identity::{
+    ManagedIdentityCredential, IMDS_TOKEN_ENDPOINT,
+};
 use crate::azure::authentication::token_store::TokenStore;
-use crate::azure::authentication::{TokenManager, TokenScope};
-use crate::conf::AzureClientConfig;
+use crate::azure::authentication::{Credential, CredentialChain, TokenScope};
+use crate::conf::{AzureClientConfig, CredentialConfig};
 use crate::{load_cert, load_priv_key, SimpleError, SimpleResult};
 use azure_core::{HttpClient, HttpError, Request, Response};
-use log::info;
+use http::StatusCode;
+use log::{info, warn};
 use oauth2::AccessToken;
-use openssl::pkey::{PKey, Private};
-use openssl::x509::X509;
 use reqwest::Client;
 use serde::{Deserialize, Deserializer};
 use serenity::prelude::TypeMapKey;
 use std::fmt::{Display, Formatter};
 use std::ops::Deref;
+use std::time::Duration;
 
 pub struct AzureClientKey;
 
@@ -24,40 +32,47 @@ impl TypeMapKey for AzureClientKey {
 }
 
 pub struct AzureClient {
-    token_manager: TokenManager,
+    credential: Box<dyn Credential>,
     token_store: TokenStore,
+    endpoints: Endpoints,
     http: Client,
 }
 
 impl AzureClient {
-    pub fn new(
-        directory: Directory,
-        client: ClientId,
-        cert: X509,
-        key: PKey<Private>,
-        http: Client,
-    ) -> AzureClient {
+    pub fn new(credential: Box<dyn Credential>, endpoints: Endpoints, http: Client) -> AzureClient {
         AzureClient {
-            token_manager: TokenManager::new(directory, client, cert, key),
+            credential,
             token_store: TokenStore::default(),
+            endpoints,
             http,
         }
     }
 
+    /// Refreshes tokens the given time before they expire.
+    pub fn token_refresh_skew(mut self, skew: Duration) -> Self {
+        self.token_store = self.token_store.refresh_skew(skew);
+        self
+    }
+
+    /// Gives up on token refreshes taking longer than the given time.
+    pub fn token_refresh_timeout(mut self, timeout: Duration) -> Self {
+        self.token_store = self.token_store.refresh_timeout(timeout);
+        self
+    }
+
     async fn token(&self, scope: TokenScope) -> SimpleResult<AccessToken> {
-        if let Some(token) = self.token_store.token(scope).await {
-            Ok(token)
-        } else {
-            info!(
-                "Requesting new Azure API Token for scope {}.",
-                scope.scope()
-            );
+        self.token_store
+            .token(scope, move || async move {
+                info!(
+                    "Requesting new Azure API Token for scope {}.",
+                    scope.scope(&self.endpoints)
+                );
 
-            let tr = self.token_manager.request_new(&self.http, scope).await?;
-            let token = tr.token.clone();
-            self.token_store.insert_token(scope, tr).await;
-            Ok(token)
-        }
+                self.credential
+                    .request_token(&self.http, &self.endpoints, scope)
+                    .await
+            })
+            .await
     }
 
     async fn send_authorized_request(
@@ -65,8 +80,21 @@ impl AzureClient {
         mut request: Request,
         scope: TokenScope,
     ) -> SimpleResult<Response> {
-        self.add_token_to_header(&mut request, scope).await?;
-        let response = self.http.execute_request2(&request).await?;
+        let token = self.token(scope).await?;
+        add_token_to_header(&mut request, &token);
+        let mut response = self.http.execute_request2(&request).await?;
+
+        // The token may have been revoked or expired on the way, it is refreshed once
+        if response.status() == StatusCode::UNAUTHORIZED {
+            info!(
+                "Azure API Token for scope {:?} was rejected, retrying with a new one.",
+                scope
+            );
+            self.token_store.invalidate(scope, &token);
+            add_token_to_header(&mut request, &self.token(scope).await?);
+            response = self.http.execute_request2(&request).await?;
+        }
+
         if response.status().is_success() {
             Ok(response)
         } else {
@@ -78,18 +106,56 @@ impl AzureClient {
             )))
         }
     }
+}
 
-    async fn add_token_to_header(
-        &self,
-        request: &mut Request,
-        scope: TokenScope,
-    ) -> SimpleResult<()> {
-        let auth_header = format!("Bearer {}", self.token(scope).await?.secret());
-        request
-            .headers_mut()
-            .insert("Authorization", auth_header.try_into().unwrap());
+fn add_token_to_header(request: &mut Request, token: &AccessToken) {
+    let auth_header = format!("Bearer {}", token.secret());
+    request
+        .headers_mut()
+        .insert("Authorization", auth_header.try_into().unwrap());
+}
 
-        Ok(())
+/// Base urls of the azure apis, without trailing slash.
+#[derive(Debug, Clone)]
+pub struct Endpoints {
+    /// Active directory authority issuing the tokens.
+    pub authority: String,
+    pub resource_manager: String,
+    /// Scope of the tokens for the resource manager.
+    pub management_scope: String,
+}
+
+/// National cloud the bot manages its vms in.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum AzureEnvironment {
+    Public,
+    China,
+    UsGov,
+}
+
+impl AzureEnvironment {
+    pub fn endpoints(self) -> Endpoints {
+        let (authority, resource_manager) = match self {
+            AzureEnvironment::Public => (
+                "https://login.microsoftonline.com",
+                "https://management.azure.com",
+            ),
+            AzureEnvironment::China => (
+                "https://login.chinacloudapi.cn",
+                "https://management.chinacloudapi.cn",
+            ),
+            AzureEnvironment::UsGov => (
+                "https://login.microsoftonline.us",
+                "https://management.usgovcloudapi.net",
+            ),
+        };
+
+        Endpoints {
+            authority: authority.to_owned(),
+            resource_manager: resource_manager.to_owned(),
+            management_scope: format!("{}/.default", resource_manager),
+        }
     }
 }
 
@@ -199,13 +265,279 @@ impl<'de> Deserialize<'de> for AzureName {
 }
 
 pub fn new_azure_client(http: Client, conf: &AzureClientConfig) -> AzureClient {
-    let x509 = load_cert(&conf.cert_path).expect("Certificate not found.");
-    let secret = load_priv_key(&conf.cert_key).expect("Private Key not found.");
-    AzureClient::new(
-        conf.directory.clone(),
-        conf.client.clone(),
-        x509,
-        secret,
+    let credentials = conf
+        .credentials()
+        .iter()
+        .filter_map(|c| match credential(conf, c) {
+            Ok(credential) => Some(credential),
+            Err(why) => {
+                warn!("Skipping azure credential {}: {}", c.name(), why);
+                None
+            }
+        })
+        .collect::<Vec<_>>();
+
+    if credentials.is_empty() {
+        panic!("No usable azure credential configured.");
+    }
+
+    let client = AzureClient::new(
+        Box::new(CredentialChain(credentials)),
+        conf.endpoints(),
         http,
-    )
+    );
+
+    let client = match conf.token_refresh_skew {
+        Some(skew) => client.token_refresh_skew(Duration::from_secs(skew)),
+        None => client,
+    };
+
+    match conf.token_refresh_timeout {
+        Some(timeout) => client.token_refresh_timeout(Duration::from_secs(timeout)),
+        None => client,
+    }
+}
+
+fn credential(
+    conf: &AzureClientConfig,
+    credential: &CredentialConfig,
+) -> SimpleResult<Box<dyn Credential>> {
+    let app = || match (&conf.directory, &conf.client) {
+        (Some(directory), Some(client)) => Ok((directory.clone(), client.clone())),
+        _ => Err(SimpleError::AuthenticationFailed(
+            "Directory and client of the app registration are required.".to_owned(),
+        )),
+    };
+
+    Ok(match credential {
+        CredentialConfig::Certificate {
+            cert_path,
+            cert_key,
+        } => {
+            let (directory, client) = app()?;
+            Box::new(CertificateCredential::new(
+                directory,
+                client,
+                load_cert(cert_path)?,
+                load_priv_key(cert_key)?,
+            ))
+        }
+        CredentialConfig::ClientSecret { secret } => {
+            let (directory, client) = app()?;
+            Box::new(ClientSecretCredential::new(
+                directory,
+                client,
+                secret.clone(),
+            ))
+        }
+        CredentialConfig::ManagedIdentity { endpoint, client } => {
+            Box::new(ManagedIdentityCredential::new(
+                endpoint
+                    .clone()
+                    .unwrap_or_else(|| IMDS_TOKEN_ENDPOINT.to_owned()),
+                client.clone(),
+            ))
+        }
+        CredentialConfig::FederatedToken { token_file } => {
+            let (directory, client) = app()?;
+            let token_file = match token_file {
+                Some(token_file) => token_file.clone(),
+                None => std::env::var_os("AZURE_FEDERATED_TOKEN_FILE")
+                    .ok_or_else(|| {
+                        SimpleError::AuthenticationFailed(
+                            "No federated token file configured.".to_owned(),
+                        )
+                    })?
+                    .into(),
+            };
+            Box::new(FederatedTokenCredential::new(directory, client, token_file))
+        }
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::azure::authentication::token_store::TokenStore;
+    use crate::azure::authentication::TokenScope;
+    use crate::azure::fake::{FakeAzure, OPERATION, RESOURCE_GROUP, SUBSCRIPTION, TOKEN, VM};
+    use crate::azure::management::vm::{InstanceView, PowerState, VmClient};
+    use crate::azure::management::vm_run_cmd::{ShellCommand, VmRunCmdClient};
+    use crate::azure::AzureClient;
+    use crate::{SimpleError, SimpleResult};
+    use azure_core::auth::TokenResponse;
+    use azure_core::HttpError;
+    use chrono::Utc;
+    use http::StatusCode;
+    use oauth2::AccessToken;
+    use std::time::Duration;
+
+    /// Store refreshing every token, with one valid for an hour cached.
+    async fn store_with_token() -> TokenStore {
+        let store = TokenStore::default()
+            .refresh_skew(Duration::from_secs(7200))
+            .refresh_timeout(Duration::from_millis(100));
+        store
+            .token(TokenScope::Management, || async {
+                Ok(TokenResponse {
+                    token: AccessToken::new("cached".to_owned()),
+                    expires_on: Utc::now() + chrono::Duration::hours(1),
+                })
+            })
+            .await
+            .unwrap();
+
+        store
+    }
+
+    async fn instance_view(client: &AzureClient) -> SimpleResult<InstanceView> {
+        client
+            .instance_view(
+                &SUBSCRIPTION.to_owned().into(),
+                &RESOURCE_GROUP.to_owned().into(),
+                &VM.to_owned().into(),
+            )
+            .await
+    }
+
+    #[tokio::test]
+    async fn reuses_token() {
+        let fake = FakeAzure::start("running").await;
+        let client = fake.client();
+
+        for _ in 0..2 {
+            let view = instance_view(&client).await.unwrap();
+            assert_eq!(view.power_state(), PowerState::Running);
+        }
+
+        assert_eq!(fake.tokens_issued(), 1);
+    }
+
+    #[tokio::test]
+    async fn concurrent_requests_share_token_request() {
+        let fake = FakeAzure::start("running").await;
+        let client = fake.client();
+
+        let views = futures::future::join_all((0..5).map(|_| instance_view(&client))).await;
+
+        assert!(views.iter().all(Result::is_ok));
+        assert_eq!(fake.tokens_issued(), 1);
+    }
+
+    #[tokio::test]
+    async fn refreshes_token_ahead_of_expiry() {
+        let fake = FakeAzure::start("running").await;
+        // The fake issues tokens valid for an hour
+        let client = fake.client().token_refresh_skew(Duration::from_secs(7200));
+
+        instance_view(&client).await.unwrap();
+        instance_view(&client).await.unwrap();
+
+        assert_eq!(fake.tokens_issued(), 2);
+    }
+
+    #[tokio::test]
+    async fn failed_refresh_keeps_valid_token() {
+        let fake = FakeAzure::start("running").await;
+        let client = fake.client().token_refresh_skew(Duration::from_secs(7200));
+
+        instance_view(&client).await.unwrap();
+        fake.fail(TOKEN, StatusCode::INTERNAL_SERVER_ERROR);
+
+        assert!(instance_view(&client).await.is_ok());
+        assert_eq!(fake.tokens_issued(), 1);
+    }
+
+    #[tokio::test]
+    async fn failing_run_command_poll_fails() {
+        let fake = FakeAzure::start("running").await;
+        fake.fail(OPERATION, StatusCode::INTERNAL_SERVER_ERROR);
+        let client = fake.client();
+
+        let script = ShellCommand {
+            script: ["echo test"],
+            parameters: Vec::new(),
+        };
+        let res = client
+            .run(
+                &SUBSCRIPTION.to_owned().into(),
+                &RESOURCE_GROUP.to_owned().into(),
+                &VM.to_owned().into(),
+                script,
+            )
+            .await
+            .unwrap()
+            .timeout(Some(Duration::from_secs(10)))
+            .wait()
+            .await;
+
+        match res {
+            Err(SimpleError::AzCoreError(azure_core::Error::Http(HttpError::StatusCode {
+                status,
+                ..
+            }))) => assert_eq!(status, StatusCode::INTERNAL_SERVER_ERROR),
+            other => panic!("Expected the failed poll, got {:?}", other),
+        }
+        assert_eq!(
+            fake.requests(),
+            ["POST token", "POST runCommand", "GET operation"]
+        );
+    }
+
+    #[tokio::test]
+    async fn hung_refresh_times_out_to_valid_token() {
+        let store = store_with_token().await;
+
+        let token = store
+            .token(TokenScope::Management, std::future::pending)
+            .await
+            .unwrap();
+
+        assert_eq!(token.secret(), "cached");
+    }
+
+    #[tokio::test]
+    async fn lookups_dont_wait_for_running_refresh() {
+        let store = store_with_token().await;
+
+        tokio::select! {
+            biased;
+            _ = store.token(TokenScope::Management, std::future::pending) => {
+                panic!("The refresh never finishes.")
+            }
+            token = store.token(TokenScope::Management, std::future::pending) => {
+                assert_eq!(token.unwrap().secret(), "cached");
+            }
+        }
+    }
+
+    #[tokio::test]
+    async fn retries_once_with_new_token_after_rejection() {
+        let fake = FakeAzure::start("running").await;
+        let client = fake.client();
+
+        instance_view(&client).await.unwrap();
+        fake.revoke_tokens();
+
+        assert!(instance_view(&client).await.is_ok());
+        assert_eq!(
+            fake.requests(),
+            [
+                "POST token",
+                "GET instanceView",
+                "GET instanceView",
+                "POST token",
+                "GET instanceView",
+            ]
+        );
+    }
+
+    #[tokio::test]
+    async fn rejected_token_request_fails() {
+        let fake = FakeAzure::start("running").await;
+        fake.fail(TOKEN, StatusCode::UNAUTHORIZED);
+        let client = fake.client();
+
+        assert!(instance_view(&client).await.is_err());
+        assert_eq!(fake.requests(), ["POST token"]);
+    }
 }
@@ -1,141 +1,82 @@
+pub mod certificate;
+pub mod client_secret;
+pub mod federated_token;
+pub mod managed_identity;
 pub mod token_store;
 
-use crate::azure::{ClientId, Directory};
-use crate::SimpleResult;
+use crate::azure::{ClientId, Directory, Endpoints};
+use crate::{SimpleError, SimpleResult};
+use async_trait::async_trait;
 use azure_core::auth::TokenResponse;
-use azure_core::HttpClient;
+use azure_core::{HttpClient, HttpError};
 use bytes::Bytes;
 use chrono::Utc;
 use http::Request;
-use jwt::claims::SecondsSinceEpoch;
-use jwt::token::Signed;
-use jwt::{
-    AlgorithmType, Claims, JoseHeader, PKeyWithDigest, RegisteredClaims, SignWithKey, Token,
-};
+use log::warn;
 use oauth2::AccessToken;
-use openssl::hash::{DigestBytes, MessageDigest};
 use openssl::pkey::{PKey, Private};
 use openssl::x509::X509;
-use serde::{Deserialize, Serialize};
+use serde::{Deserialize, Deserializer};
 use std::fs;
 use std::path::Path;
-use std::time::{Duration, SystemTime};
-use uuid::Uuid;
 
-const AZ_TOKEN_ENDPOINT_BASE: &str = "https://login.microsoftonline.com";
 const AZ_TOKEN_ENDPOINT_TAIL: &str = "oauth2/v2.0/token";
+const CLIENT_ASSERTION_TYPE: &str = "urn:ietf:params:oauth:client-assertion-type:jwt-bearer";
 
-const JWT_EXP_DURATION: Duration = Duration::from_secs(60);
-
-macro_rules! token_endpoint {
-    ($tenant_id:expr) => {
+macro_rules! token_endpoint_ {
+    ($authority:expr, $tenant_id:expr) => {
         format!(
             "{}/{}/{}",
-            AZ_TOKEN_ENDPOINT_BASE,
+            $authority,
             $tenant_id.id(),
-            AZ_TOKEN_ENDPOINT_TAIL
+            $crate::azure::authentication::AZ_TOKEN_ENDPOINT_TAIL
         )
     };
 }
 
-pub struct TokenManager {
-    directory: Directory,
-    client: ClientId,
-    key: PKeyWithDigest<Private>,
-    fingerprint: DigestBytes,
-}
-
-impl TokenManager {
-    pub fn new(
-        directory: Directory,
-        client: ClientId,
-        cert: X509,
-        key: PKey<Private>,
-    ) -> TokenManager {
-        TokenManager {
-            fingerprint: cert_fingerprint_sha1(&cert),
-            key: PKeyWithDigest {
-                key,
-                digest: MessageDigest::sha256(),
-            },
-            directory,
-            client,
-        }
-    }
+use token_endpoint_ as token_endpoint;
 
-    pub async fn request_new(
+/// Source of access tokens for the azure apis.
+#[async_trait]
+pub trait Credential: Send + Sync {
+    /// Name of the credential in logs.
+    fn name(&self) -> &'static str;
+    async fn request_token(
         &self,
-        client: &dyn HttpClient,
+        http: &dyn HttpClient,
+        endpoints: &Endpoints,
         scope: TokenScope,
-    ) -> SimpleResult<TokenResponse> {
-        let req = self.generate_auth_request(scope)?.into();
-        let resp = client.execute_request2(&req).await.unwrap();
-        let body = resp.into_body_string().await;
-
-        serde_json::from_str::<ResponseBody>(&body)
-            .map(Into::into)
-            .map_err(Into::into)
-    }
-
-    fn generate_auth_request(&self, scope: TokenScope) -> SimpleResult<Request<Bytes>> {
-        const CONTENT_TYPE: &str = "application/x-www-form-urlencoded";
+    ) -> SimpleResult<TokenResponse>;
+}
 
-        let url = token_endpoint!(self.directory);
+/// Tries the credentials in order, until one of them issues a token.
+pub struct CredentialChain(pub Vec<Box<dyn Credential>>);
 
-        Ok(Request::builder()
-            .method("POST")
-            .uri(url)
-            .header("Content-Type", CONTENT_TYPE)
-            .body(self.body(scope)?)
-            .expect("Failed to build auth request."))
+#[async_trait]
+impl Credential for CredentialChain {
+    fn name(&self) -> &'static str {
+        "chain"
     }
 
-    fn body(&self, scope: TokenScope) -> SimpleResult<Bytes> {
-        const CLIENT_ASSERTION_TYPE: &str =
-            "urn:ietf:params:oauth:client-assertion-type:jwt-bearer";
-        const GRANT_TYPE: &str = "client_credentials";
-
-        macro_rules! body_key_value {
-            ($key:expr, $value:expr) => {
-                format!("{}={}", $key, urlencoding::encode($value))
-            };
+    async fn request_token(
+        &self,
+        http: &dyn HttpClient,
+        endpoints: &Endpoints,
+        scope: TokenScope,
+    ) -> SimpleResult<TokenResponse> {
+        let mut errors = Vec::new();
+
+        for credential in &self.0 {
+            match credential.request_token(http, endpoints, scope).await {
+                Ok(token) => return Ok(token),
+                Err(why) => {
+                    warn!("Azure credential {} failed: {}", credential.name(), why);
+                    errors.push(format!("{}: {}", credential.name(), why));
+                }
+            }
         }
 
-        let token = body_key_value!("client_assertion", self.jwt_token()?.as_str());
-        let scope = body_key_value!("scope", scope.scope());
-        let client = body_key_value!("client_id", self.client.id());
-        let assertion_type = body_key_value!("client_assertion_type", CLIENT_ASSERTION_TYPE);
-        let grant_type = body_key_value!("grant_type", GRANT_TYPE);
-
-        Ok([scope, client, assertion_type, token, grant_type]
-            .join("&")
-            .into_bytes()
-            .into())
-    }
-
-    fn jwt_token(&self) -> SimpleResult<Token<Header, Claims, Signed>> {
-        let header = Header::with_fingerprint(&self.fingerprint);
-        let claims = self.jwt_claims();
-
-        let token = Token::new(header, claims);
-
-        token.sign_with_key(&self.key).map_err(Into::into)
-    }
-
-    fn jwt_claims(&self) -> Claims {
-        let (now, exp) = jwt_now_exp();
-
-        let reg = RegisteredClaims {
-            audience: Some(token_endpoint!(self.directory)),
-            issuer: Some(self.client.id().to_string()),
-            subject: Some(self.client.id().to_string()),
-            not_before: Some(now),
-            expiration: Some(exp),
-            json_web_token_id: Some(jti()),
-            issued_at: Some(now),
-        };
-
-        Claims::new(reg)
+        Err(SimpleError::AuthenticationFailed(errors.join(", ")))
     }
 }
 
@@ -145,48 +86,96 @@ pub enum TokenScope {
 }
 
 impl TokenScope {
-    pub fn scope(self) -> &'static str {
+    /// Scope requested from the authority, which depends on the cloud.
+    pub fn scope(self, endpoints: &Endpoints) -> &str {
         match self {
-            TokenScope::Management => "https://management.azure.com/.default",
+            TokenScope::Management => &endpoints.management_scope,
         }
     }
-}
 
-#[derive(Serialize)]
-struct Header {
-    alg: AlgorithmType,
-    typ: Type,
-    x5t: String,
+    /// Resource of the scope, for endpoints not supporting scopes like the instance metadata service.
+    pub fn resource(self, endpoints: &Endpoints) -> &str {
+        let scope = self.scope(endpoints);
+        scope.strip_suffix("/.default").unwrap_or(scope)
+    }
 }
 
-impl Header {
-    fn with_fingerprint(digest: &[u8]) -> Header {
-        Header {
-            alg: AlgorithmType::Rs256,
-            typ: Type::Jwt,
-            x5t: base64::encode(digest),
-        }
-    }
+/// Requests a token by the client credentials grant, the client authenticates itself by the given fields.
+async fn client_credentials(
+    http: &dyn HttpClient,
+    endpoints: &Endpoints,
+    directory: &Directory,
+    client: &ClientId,
+    scope: TokenScope,
+    authentication: &[(&str, &str)],
+) -> SimpleResult<TokenResponse> {
+    const CONTENT_TYPE: &str = "application/x-www-form-urlencoded";
+
+    let mut fields = vec![
+        ("scope", scope.scope(endpoints)),
+        ("client_id", client.id()),
+        ("grant_type", "client_credentials"),
+    ];
+    fields.extend_from_slice(authentication);
+
+    let body = fields
+        .iter()
+        .map(|(key, value)| format!("{}={}", key, urlencoding::encode(value)))
+        .collect::<Vec<_>>()
+        .join("&");
+
+    let request = Request::builder()
+        .method("POST")
+        .uri(token_endpoint!(endpoints.authority, directory))
+        .header("Content-Type", CONTENT_TYPE)
+        .body(Bytes::from(body))
+        .expect("Failed to build auth request.");
+
+    execute_token_request(http, request).await
 }
 
-impl JoseHeader for Header {
-    fn algorithm_type(&self) -> AlgorithmType {
-        self.alg
+async fn execute_token_request(
+    http: &dyn HttpClient,
+    request: Request<Bytes>,
+) -> SimpleResult<TokenResponse> {
+    let resp = http.execute_request2(&request.into()).await?;
+    let status = resp.status();
+    let body = resp.into_body_string().await;
+
+    if !status.is_success() {
+        return Err(HttpError::StatusCode { status, body }.into());
     }
-}
 
-#[derive(Serialize)]
-enum Type {
-    #[serde(rename = "JWT")]
-    Jwt,
+    serde_json::from_str::<ResponseBody>(&body)
+        .map(Into::into)
+        .map_err(Into::into)
 }
 
 #[derive(Deserialize)]
 struct ResponseBody {
+    /// The instance metadata service sends a string.
+    #[serde(deserialize_with = "deserialize_seconds")]
     expires_in: i64,
     access_token: String,
 }
 
+fn deserialize_seconds<'de, D>(d: D) -> Result<i64, D::Error>
+where
+    D: Deserializer<'de>,
+{
+    #[derive(Deserialize)]
+    #[serde(untagged)]
+    enum Seconds {
+        Number(i64),
+        String(String),
+    }
+
+    match Seconds::deserialize(d)? {
+        Seconds::Number(seconds) => Ok(seconds),
+        Seconds::String(seconds) => seconds.parse().map_err(serde::de::Error::custom),
+    }
+}
+
 impl From<ResponseBody> for TokenResponse {
     fn from(resp: ResponseBody) -> TokenResponse {
         let now = Utc::now();
@@ -199,19 +188,6 @@ impl From<ResponseBody> for TokenResponse {
     }
 }
 
-fn jwt_now_exp() -> (SecondsSinceEpoch, SecondsSinceEpoch) {
-    let now = SystemTime::now();
-    let now_anchored = now.duration_since(SystemTime::UNIX_EPOCH).unwrap();
-    let exp = now + JWT_EXP_DURATION;
-    let exp_anchored = exp.duration_since(SystemTime::UNIX_EPOCH).unwrap();
-
-    (now_anchored.as_secs(), exp_anchored.as_secs())
-}
-
-fn jti() -> String {
-    Uuid::new_v4().to_string()
-}
-
 fn load_cert_buf(path: impl AsRef<Path>) -> SimpleResult<Vec<u8>> {
     fs::read(path).map_err(Into::into)
 }
@@ -220,10 +196,6 @@ pub fn load_cert(path: impl AsRef<Path>) -> SimpleResult<X509> {
     X509::from_pem(&load_cert_buf(path)?).map_err(Into::into)
 }
 
-fn cert_fingerprint_sha1(cert: &X509) -> DigestBytes {
-    cert.digest(MessageDigest::sha1()).unwrap()
-}
-
 fn load_priv_key_bytes(path: impl AsRef<Path>) -> SimpleResult<Vec<u8>> {
     fs::read(path).map_err(Into::into)
 }
@@ -231,3 +203,93 @@ fn load_priv_key_bytes(path: impl AsRef<Path>) -> SimpleResult<Vec<u8>> {
 pub fn load_priv_key(path: impl AsRef<Path>) -> SimpleResult<PKey<Private>> {
     PKey::private_key_from_pem(&load_priv_key_bytes(path)?).map_err(Into::into)
 }
+
+#[cfg(test)]
+mod tests {
+    use super::client_secret::ClientSecretCredential;
+    use super::federated_token::FederatedTokenCredential;
+    use super::*;
+    use crate::azure::fake::{FakeAzure, IDENTITY, TENANT};
+    use http::StatusCode;
+    use uuid::Uuid;
+
+    fn client_secret() -> ClientSecretCredential {
+        ClientSecretCredential::new(
+            TENANT.to_owned().into(),
+            "client".to_owned().into(),
+            "secret".to_owned(),
+        )
+    }
+
+    async fn request(fake: &FakeAzure, credential: &dyn Credential) -> SimpleResult<TokenResponse> {
+        credential
+            .request_token(
+                &reqwest::Client::new(),
+                &fake.endpoints(),
+                TokenScope::Management,
+            )
+            .await
+    }
+
+    #[tokio::test]
+    async fn managed_identity_requests_resource() {
+        let fake = FakeAzure::start("running").await;
+
+        let token = request(&fake, &fake.managed_identity()).await.unwrap();
+
+        assert_eq!(token.token.secret(), "fake-token-1");
+        assert!(token.expires_on > Utc::now());
+        assert_eq!(fake.requests(), ["GET identity"]);
+    }
+
+    #[tokio::test]
+    async fn federated_token_reads_file() {
+        let fake = FakeAzure::start("running").await;
+        let token_file = std::env::temp_dir().join(format!("r6v3-{}.jwt", Uuid::new_v4()));
+        fs::write(&token_file, "federated\n").unwrap();
+        let credential = FederatedTokenCredential::new(
+            TENANT.to_owned().into(),
+            "client".to_owned().into(),
+            token_file,
+        );
+
+        assert!(request(&fake, &credential).await.is_ok());
+        assert_eq!(fake.requests(), ["POST token"]);
+    }
+
+    #[tokio::test]
+    async fn chain_falls_back_to_next_credential() {
+        let fake = FakeAzure::start("running").await;
+        fake.fail(IDENTITY, StatusCode::BAD_REQUEST);
+        let chain = CredentialChain(vec![
+            Box::new(fake.managed_identity()),
+            Box::new(client_secret()),
+        ]);
+
+        assert!(request(&fake, &chain).await.is_ok());
+        assert_eq!(fake.requests(), ["GET identity", "POST token"]);
+    }
+
+    #[tokio::test]
+    async fn chain_fails_without_usable_credential() {
+        let fake = FakeAzure::start("running").await;
+        fake.fail(IDENTITY, StatusCode::BAD_REQUEST);
+        let missing = FederatedTokenCredential::new(
+            TENANT.to_owned().into(),
+            "client".to_owned().into(),
+            std::env::temp_dir().join(format!("r6v3-{}.jwt", Uuid::new_v4())),
+        );
+        let chain = CredentialChain(vec![Box::new(fake.managed_identity()), Box::new(missing)]);
+
+        match request(&fake, &chain).await {
+            Err(SimpleError::AuthenticationFailed(why)) => {
+                assert!(why.contains("managed identity"), "{}", why);
+                assert!(why.contains("federated token"), "{}", why);
+            }
+            other => panic!(
+                "Expected an authentication error, got {:?}",
+                other.map(|_| ())
+            ),
+        }
+    }
+}
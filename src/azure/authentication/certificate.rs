@@ -0,0 +1,148 @@
+use crate::azure::authentication::{
+    client_credentials, token_endpoint, Credential, TokenScope, CLIENT_ASSERTION_TYPE,
+};
+use crate::azure::{ClientId, Directory, Endpoints};
+use crate::SimpleResult;
+use async_trait::async_trait;
+use azure_core::auth::TokenResponse;
+use azure_core::HttpClient;
+use jwt::claims::SecondsSinceEpoch;
+use jwt::token::Signed;
+use jwt::{
+    AlgorithmType, Claims, JoseHeader, PKeyWithDigest, RegisteredClaims, SignWithKey, Token,
+};
+use openssl::hash::{DigestBytes, MessageDigest};
+use openssl::pkey::{PKey, Private};
+use openssl::x509::X509;
+use serde::Serialize;
+use std::time::{Duration, SystemTime};
+use uuid::Uuid;
+
+const JWT_EXP_DURATION: Duration = Duration::from_secs(60);
+
+/// App registration authenticating by a client assertion signed with its certificate.
+pub struct CertificateCredential {
+    directory: Directory,
+    client: ClientId,
+    key: PKeyWithDigest<Private>,
+    fingerprint: DigestBytes,
+}
+
+impl CertificateCredential {
+    pub fn new(
+        directory: Directory,
+        client: ClientId,
+        cert: X509,
+        key: PKey<Private>,
+    ) -> CertificateCredential {
+        CertificateCredential {
+            fingerprint: cert_fingerprint_sha1(&cert),
+            key: PKeyWithDigest {
+                key,
+                digest: MessageDigest::sha256(),
+            },
+            directory,
+            client,
+        }
+    }
+
+    fn jwt_token(&self, endpoints: &Endpoints) -> SimpleResult<Token<Header, Claims, Signed>> {
+        let header = Header::with_fingerprint(&self.fingerprint);
+        let claims = self.jwt_claims(endpoints);
+
+        let token = Token::new(header, claims);
+
+        token.sign_with_key(&self.key).map_err(Into::into)
+    }
+
+    fn jwt_claims(&self, endpoints: &Endpoints) -> Claims {
+        let (now, exp) = jwt_now_exp();
+
+        let reg = RegisteredClaims {
+            audience: Some(token_endpoint!(endpoints.authority, self.directory)),
+            issuer: Some(self.client.id().to_string()),
+            subject: Some(self.client.id().to_string()),
+            not_before: Some(now),
+            expiration: Some(exp),
+            json_web_token_id: Some(jti()),
+            issued_at: Some(now),
+        };
+
+        Claims::new(reg)
+    }
+}
+
+#[async_trait]
+impl Credential for CertificateCredential {
+    fn name(&self) -> &'static str {
+        "certificate"
+    }
+
+    async fn request_token(
+        &self,
+        http: &dyn HttpClient,
+        endpoints: &Endpoints,
+        scope: TokenScope,
+    ) -> SimpleResult<TokenResponse> {
+        let assertion = self.jwt_token(endpoints)?;
+
+        client_credentials(
+            http,
+            endpoints,
+            &self.directory,
+            &self.client,
+            scope,
+            &[
+                ("client_assertion_type", CLIENT_ASSERTION_TYPE),
+                ("client_assertion", assertion.as_str()),
+            ],
+        )
+        .await
+    }
+}
+
+#[derive(Serialize)]
+struct Header {
+    alg: AlgorithmType,
+    typ: Type,
+    x5t: String,
+}
+
+impl Header {
+    fn with_fingerprint(digest: &[u8]) -> Header {
+        Header {
+            alg: AlgorithmType::Rs256,
+            typ: Type::Jwt,
+            x5t: base64::encode(digest),
+        }
+    }
+}
+
+impl JoseHeader for Header {
+    fn algorithm_type(&self) -> AlgorithmType {
+        self.alg
+    }
+}
+
+#[derive(Serialize)]
+enum Type {
+    #[serde(rename = "JWT")]
+    Jwt,
+}
+
+fn jwt_now_exp() -> (SecondsSinceEpoch, SecondsSinceEpoch) {
+    let now = SystemTime::now();
+    let now_anchored = now.duration_since(SystemTime::UNIX_EPOCH).unwrap();
+    let exp = now + JWT_EXP_DURATION;
+    let exp_anchored = exp.duration_since(SystemTime::UNIX_EPOCH).unwrap();
+
+    (now_anchored.as_secs(), exp_anchored.as_secs())
+}
+
+fn jti() -> String {
+    Uuid::new_v4().to_string()
+}
+
+fn cert_fingerprint_sha1(cert: &X509) -> DigestBytes {
+    cert.digest(MessageDigest::sha1()).unwrap()
+}
@@ -0,0 +1,47 @@
+use crate::azure::authentication::{client_credentials, Credential, TokenScope};
+use crate::azure::{ClientId, Directory, Endpoints};
+use crate::SimpleResult;
+use async_trait::async_trait;
+use azure_core::auth::TokenResponse;
+use azure_core::HttpClient;
+
+/// App registration authenticating by a client secret.
+pub struct ClientSecretCredential {
+    directory: Directory,
+    client: ClientId,
+    secret: String,
+}
+
+impl ClientSecretCredential {
+    pub fn new(directory: Directory, client: ClientId, secret: String) -> ClientSecretCredential {
+        ClientSecretCredential {
+            directory,
+            client,
+            secret,
+        }
+    }
+}
+
+#[async_trait]
+impl Credential for ClientSecretCredential {
+    fn name(&self) -> &'static str {
+        "client secret"
+    }
+
+    async fn request_token(
+        &self,
+        http: &dyn HttpClient,
+        endpoints: &Endpoints,
+        scope: TokenScope,
+    ) -> SimpleResult<TokenResponse> {
+        client_credentials(
+            http,
+            endpoints,
+            &self.directory,
+            &self.client,
+            scope,
+            &[("client_secret", &self.secret)],
+        )
+        .await
+    }
+}
@@ -0,0 +1,60 @@
+use crate::azure::authentication::{
+    client_credentials, Credential, TokenScope, CLIENT_ASSERTION_TYPE,
+};
+use crate::azure::{ClientId, Directory, Endpoints};
+use crate::SimpleResult;
+use async_trait::async_trait;
+use azure_core::auth::TokenResponse;
+use azure_core::HttpClient;
+use std::path::PathBuf;
+
+/// App registration trusting tokens of another identity provider, e.g. the workload identity of a kubernetes pod.
+pub struct FederatedTokenCredential {
+    directory: Directory,
+    client: ClientId,
+    token_file: PathBuf,
+}
+
+impl FederatedTokenCredential {
+    pub fn new(
+        directory: Directory,
+        client: ClientId,
+        token_file: PathBuf,
+    ) -> FederatedTokenCredential {
+        FederatedTokenCredential {
+            directory,
+            client,
+            token_file,
+        }
+    }
+}
+
+#[async_trait]
+impl Credential for FederatedTokenCredential {
+    fn name(&self) -> &'static str {
+        "federated token"
+    }
+
+    async fn request_token(
+        &self,
+        http: &dyn HttpClient,
+        endpoints: &Endpoints,
+        scope: TokenScope,
+    ) -> SimpleResult<TokenResponse> {
+        // The file is rotated while the bot is running
+        let assertion = tokio::fs::read_to_string(&self.token_file).await?;
+
+        client_credentials(
+            http,
+            endpoints,
+            &self.directory,
+            &self.client,
+            scope,
+            &[
+                ("client_assertion_type", CLIENT_ASSERTION_TYPE),
+                ("client_assertion", assertion.trim()),
+            ],
+        )
+        .await
+    }
+}
@@ -0,0 +1,60 @@
+use crate::azure::authentication::{execute_token_request, Credential, TokenScope};
+use crate::azure::{ClientId, Endpoints};
+use crate::{SimpleError, SimpleResult};
+use async_trait::async_trait;
+use azure_core::auth::TokenResponse;
+use azure_core::HttpClient;
+use bytes::Bytes;
+use http::Request;
+use std::time::Duration;
+
+pub const IMDS_TOKEN_ENDPOINT: &str = "http://169.254.169.254/metadata/identity/oauth2/token";
+const IMDS_API_VERSION: &str = "2018-02-01";
+/// Outside of azure the metadata service is unreachable, which shouldn't delay the next credential for long.
+const IMDS_TIMEOUT: Duration = Duration::from_secs(5);
+
+/// Managed identity of the azure vm the bot is running on, issued by the instance metadata service.
+pub struct ManagedIdentityCredential {
+    endpoint: String,
+    /// Client id of a user assigned identity, the system assigned one is used otherwise.
+    client: Option<ClientId>,
+}
+
+impl ManagedIdentityCredential {
+    pub fn new(endpoint: String, client: Option<ClientId>) -> ManagedIdentityCredential {
+        ManagedIdentityCredential { endpoint, client }
+    }
+}
+
+#[async_trait]
+impl Credential for ManagedIdentityCredential {
+    fn name(&self) -> &'static str {
+        "managed identity"
+    }
+
+    async fn request_token(
+        &self,
+        http: &dyn HttpClient,
+        endpoints: &Endpoints,
+        scope: TokenScope,
+    ) -> SimpleResult<TokenResponse> {
+        let mut url = format!(
+            "{}?api-version={}&resource={}",
+            self.endpoint,
+            IMDS_API_VERSION,
+            urlencoding::encode(scope.resource(endpoints))
+        );
+        if let Some(ref client) = self.client {
+            url += &format!("&client_id={}", client.id());
+        }
+
+        let request = Request::get(url)
+            .header("Metadata", "true")
+            .body(Bytes::new())
+            .expect("Failed to build auth request.");
+
+        tokio::time::timeout(IMDS_TIMEOUT, execute_token_request(http, request))
+            .await
+            .map_err(|_| SimpleError::Timeout)?
+    }
+}
@@ -1,33 +1,133 @@
 use crate::azure::authentication::TokenScope;
+use crate::{SimpleError, SimpleResult};
 use azure_core::auth::TokenResponse;
-use chrono::{Duration, Utc};
+use chrono::Utc;
+use log::warn;
 use oauth2::AccessToken;
 use std::collections::HashMap;
-use std::sync::Arc;
-use tokio::sync::RwLock;
+use std::future::Future;
+use std::sync::{Arc, RwLock};
+use std::time::Duration;
+use tokio::sync::Mutex;
 
-#[derive(Default, Clone)]
+const DEFAULT_REFRESH_SKEW: Duration = Duration::from_secs(300);
+const DEFAULT_REFRESH_TIMEOUT: Duration = Duration::from_secs(30);
+
+#[derive(Default)]
+struct Slot {
+    cached: RwLock<Option<TokenResponse>>,
+    /// Held while the token is refreshed, lookups of the cached token don't take it.
+    refresh: Mutex<()>,
+}
+
+impl Slot {
+    /// The cached token, if it doesn't expire within `margin`.
+    fn valid_for(&self, margin: chrono::Duration) -> Option<AccessToken> {
+        match *self.cached.read().unwrap() {
+            Some(ref current) if current.expires_on - margin > Utc::now() => {
+                Some(current.token.clone())
+            }
+            _ => None,
+        }
+    }
+}
+
+/// Caches a token per scope, only one refresh per scope runs at a time.
+#[derive(Clone)]
 pub struct TokenStore {
-    store: Arc<RwLock<HashMap<TokenScope, TokenResponse>>>,
+    slots: Arc<std::sync::Mutex<HashMap<TokenScope, Arc<Slot>>>>,
+    /// Time before the expiry, from which on a token is refreshed.
+    refresh_skew: chrono::Duration,
+    refresh_timeout: Duration,
+}
+
+impl Default for TokenStore {
+    fn default() -> Self {
+        TokenStore {
+            slots: Default::default(),
+            refresh_skew: chrono::Duration::zero(),
+            refresh_timeout: DEFAULT_REFRESH_TIMEOUT,
+        }
+        .refresh_skew(DEFAULT_REFRESH_SKEW)
+    }
 }
 
 impl TokenStore {
-    pub async fn token(&self, scope: TokenScope) -> Option<AccessToken> {
-        self.token_response(scope)
-            .await
-            .filter(|r| r.expires_on.signed_duration_since(Utc::now()) > Duration::zero())
-            .map(|r| r.token)
+    pub fn refresh_skew(mut self, skew: Duration) -> Self {
+        self.refresh_skew =
+            chrono::Duration::from_std(skew).expect("Token refresh skew out of range.");
+        self
     }
 
-    pub async fn token_response(&self, scope: TokenScope) -> Option<TokenResponse> {
-        self.store.read().await.get(&scope).cloned()
+    /// Gives up on refreshes taking longer than `timeout`.
+    pub fn refresh_timeout(mut self, timeout: Duration) -> Self {
+        self.refresh_timeout = timeout;
+        self
     }
 
-    pub async fn insert_token(
-        &self,
-        scope: TokenScope,
-        token: TokenResponse,
-    ) -> Option<TokenResponse> {
-        self.store.write().await.insert(scope, token)
+    fn slot(&self, scope: TokenScope) -> Arc<Slot> {
+        self.slots.lock().unwrap().entry(scope).or_default().clone()
+    }
+
+    /// Cached token of the scope, requested by `refresh` if there is none or it is about to expire.
+    ///
+    /// Only one refresh per scope runs at a time, callers arriving meanwhile get the current token while it's valid
+    /// and wait for the refresh otherwise. If the refresh fails or times out, the current token is returned as long
+    /// as it's valid.
+    pub async fn token<F, Fut>(&self, scope: TokenScope, refresh: F) -> SimpleResult<AccessToken>
+    where
+        F: FnOnce() -> Fut,
+        Fut: Future<Output = SimpleResult<TokenResponse>>,
+    {
+        let slot = self.slot(scope);
+        if let Some(token) = slot.valid_for(self.refresh_skew) {
+            return Ok(token);
+        }
+
+        let _refreshing = match slot.refresh.try_lock() {
+            Ok(guard) => guard,
+            Err(_) => {
+                if let Some(token) = slot.valid_for(chrono::Duration::zero()) {
+                    return Ok(token);
+                }
+                slot.refresh.lock().await
+            }
+        };
+
+        // The refresh this caller waited for may already have replaced the token
+        if let Some(token) = slot.valid_for(self.refresh_skew) {
+            return Ok(token);
+        }
+
+        let why = match tokio::time::timeout(self.refresh_timeout, refresh()).await {
+            Ok(Ok(refreshed)) => {
+                let token = refreshed.token.clone();
+                *slot.cached.write().unwrap() = Some(refreshed);
+                return Ok(token);
+            }
+            Ok(Err(why)) => why,
+            Err(_) => SimpleError::Timeout,
+        };
+
+        match slot.valid_for(chrono::Duration::zero()) {
+            Some(token) => {
+                warn!(
+                    "Error refreshing token for scope {:?}, using the current one: {}",
+                    scope, why
+                );
+                Ok(token)
+            }
+            None => Err(why),
+        }
+    }
+
+    /// Drops the token, e.g. after it was rejected, unless it already has been refreshed.
+    pub fn invalidate(&self, scope: TokenScope, token: &AccessToken) {
+        let slot = self.slot(scope);
+        let mut cached = slot.cached.write().unwrap();
+
+        if matches!(*cached, Some(ref current) if current.token.secret() == token.secret()) {
+            *cached = None;
+        }
     }
 }
@@ -0,0 +1,467 @@
+use crate::azure::authentication::certificate::CertificateCredential;
+use crate::azure::authentication::managed_identity::ManagedIdentityCredential;
+use crate::azure::authentication::Credential;
+use crate::azure::{AzureClient, Endpoints};
+use chrono::Utc;
+use http::StatusCode;
+use openssl::asn1::Asn1Time;
+use openssl::hash::MessageDigest;
+use openssl::pkey::PKey;
+use openssl::rsa::Rsa;
+use openssl::x509::{X509NameBuilder, X509};
+use serde_json::{json, Value};
+use std::collections::HashMap;
+use std::sync::{Arc, Mutex};
+use tokio::io::{AsyncBufReadExt, AsyncReadExt, AsyncWriteExt, BufReader};
+use tokio::net::{TcpListener, TcpStream};
+
+pub const TENANT: &str = "tenant";
+pub const SUBSCRIPTION: &str = "sub";
+pub const RESOURCE_GROUP: &str = "rg";
+pub const VM: &str = "vm";
+
+/// Routes, which can be made to fail by [`FakeAzure::fail`].
+pub const TOKEN: &str = "token";
+pub const IDENTITY: &str = "identity";
+pub const INSTANCE_VIEW: &str = "instanceView";
+pub const START: &str = "start";
+pub const DEALLOCATE: &str = "deallocate";
+pub const RUN_COMMAND: &str = "runCommand";
+pub const OPERATION: &str = "operation";
+
+/// Stand-in for the azure active directory and resource manager, serving a single vm over plain http.
+pub struct FakeAzure {
+    url: String,
+    state: Arc<Mutex<State>>,
+}
+
+struct State {
+    url: String,
+    power_state: &'static str,
+    /// Polls answered with 202 before an operation completes.
+    polls: usize,
+    operations: HashMap<u64, Operation>,
+    next_operation: u64,
+    failures: HashMap<&'static str, StatusCode>,
+    script_exit: i32,
+    tokens: usize,
+    /// Tokens up to this number are rejected.
+    revoked: usize,
+    requests: Vec<String>,
+}
+
+struct Operation {
+    polls: usize,
+    completion: Completion,
+}
+
+enum Completion {
+    Power(&'static str),
+    Script(i32),
+}
+
+struct Response {
+    status: StatusCode,
+    location: Option<String>,
+    body: Value,
+}
+
+impl Response {
+    fn json(status: StatusCode, body: Value) -> Self {
+        Response {
+            status,
+            location: None,
+            body,
+        }
+    }
+
+    fn error(status: StatusCode, code: &str) -> Self {
+        Response::json(
+            status,
+            json!({ "error": { "code": code, "message": format!("Fake {}", code) } }),
+        )
+    }
+}
+
+impl FakeAzure {
+    /// Serves a vm in the given power state on a random local port.
+    pub async fn start(power_state: &'static str) -> FakeAzure {
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let url = format!("http://{}", listener.local_addr().unwrap());
+
+        let state = Arc::new(Mutex::new(State {
+            url: url.clone(),
+            power_state,
+            polls: 1,
+            operations: HashMap::new(),
+            next_operation: 0,
+            failures: HashMap::new(),
+            script_exit: 0,
+            tokens: 0,
+            revoked: 0,
+            requests: Vec::new(),
+        }));
+
+        let accepting = state.clone();
+        tokio::spawn(async move {
+            while let Ok((stream, _)) = listener.accept().await {
+                tokio::spawn(serve(stream, accepting.clone()));
+            }
+        });
+
+        FakeAzure { url, state }
+    }
+
+    /// Client authenticating with a freshly generated certificate.
+    pub fn client(&self) -> AzureClient {
+        self.client_with(Box::new(self.certificate()))
+    }
+
+    pub fn client_with(&self, credential: Box<dyn Credential>) -> AzureClient {
+        AzureClient::new(credential, self.endpoints(), reqwest::Client::new())
+    }
+
+    pub fn certificate(&self) -> CertificateCredential {
+        let key = PKey::from_rsa(Rsa::generate(2048).unwrap()).unwrap();
+
+        let mut name = X509NameBuilder::new().unwrap();
+        name.append_entry_by_text("CN", "r6v3-test").unwrap();
+        let name = name.build();
+
+        let mut cert = X509::builder().unwrap();
+        cert.set_version(2).unwrap();
+        cert.set_subject_name(&name).unwrap();
+        cert.set_issuer_name(&name).unwrap();
+        cert.set_pubkey(&key).unwrap();
+        cert.set_not_before(&Asn1Time::days_from_now(0).unwrap())
+            .unwrap();
+        cert.set_not_after(&Asn1Time::days_from_now(1).unwrap())
+            .unwrap();
+        cert.sign(&key, MessageDigest::sha256()).unwrap();
+
+        CertificateCredential::new(
+            TENANT.to_owned().into(),
+            "client".to_owned().into(),
+            cert.build(),
+            key,
+        )
+    }
+
+    /// Managed identity issued by the metadata service of the fake.
+    pub fn managed_identity(&self) -> ManagedIdentityCredential {
+        ManagedIdentityCredential::new(format!("{}/metadata/identity/oauth2/token", self.url), None)
+    }
+
+    pub fn endpoints(&self) -> Endpoints {
+        Endpoints {
+            authority: self.url.clone(),
+            resource_manager: self.url.clone(),
+            management_scope: format!("{}/.default", self.url),
+        }
+    }
+
+    pub fn power_state(&self) -> &'static str {
+        self.state.lock().unwrap().power_state
+    }
+
+    /// Answers every request to the route with the status code.
+    pub fn fail(&self, route: &'static str, status: StatusCode) {
+        self.state.lock().unwrap().failures.insert(route, status);
+    }
+
+    /// Exit status of the scripts run from now on.
+    pub fn script_exit(&self, status: i32) {
+        self.state.lock().unwrap().script_exit = status;
+    }
+
+    /// Rejects the tokens issued so far.
+    pub fn revoke_tokens(&self) {
+        let mut state = self.state.lock().unwrap();
+        state.revoked = state.tokens;
+    }
+
+    pub fn tokens_issued(&self) -> usize {
+        self.state.lock().unwrap().tokens
+    }
+
+    /// Method and route of every request received so far.
+    pub fn requests(&self) -> Vec<String> {
+        self.state.lock().unwrap().requests.clone()
+    }
+}
+
+async fn serve(mut stream: TcpStream, state: Arc<Mutex<State>>) -> std::io::Result<()> {
+    let (read, mut write) = stream.split();
+    let mut reader = BufReader::new(read);
+
+    let mut line = String::new();
+    reader.read_line(&mut line).await?;
+    let mut request_line = line.split_whitespace();
+    let method = request_line.next().unwrap_or_default().to_owned();
+    let target = request_line.next().unwrap_or_default().to_owned();
+
+    let mut headers = HashMap::new();
+    loop {
+        line.clear();
+        reader.read_line(&mut line).await?;
+        match line.trim_end().split_once(':') {
+            Some((name, value)) => {
+                headers.insert(name.to_ascii_lowercase(), value.trim().to_owned());
+            }
+            None => break,
+        }
+    }
+
+    let len = headers
+        .get("content-length")
+        .and_then(|l| l.parse().ok())
+        .unwrap_or(0);
+    let mut body = vec![0; len];
+    reader.read_exact(&mut body).await?;
+
+    let response =
+        state
+            .lock()
+            .unwrap()
+            .route(&method, &target, &headers, &String::from_utf8_lossy(&body));
+
+    let body = response.body.to_string();
+    let mut head = format!(
+        "HTTP/1.1 {} {}\r\nContent-Type: application/json\r\nContent-Length: {}\r\nConnection: close\r\n",
+        response.status.as_u16(),
+        response.status.canonical_reason().unwrap_or_default(),
+        body.len()
+    );
+    if let Some(location) = response.location {
+        head += &format!("Location: {}\r\n", location);
+    }
+
+    write.write_all(head.as_bytes()).await?;
+    write.write_all(b"\r\n").await?;
+    write.write_all(body.as_bytes()).await?;
+    write.shutdown().await
+}
+
+impl State {
+    fn route(
+        &mut self,
+        method: &str,
+        target: &str,
+        headers: &HashMap<String, String>,
+        body: &str,
+    ) -> Response {
+        let (path, query) = target.split_once('?').unwrap_or((target, ""));
+        let parts = path.trim_start_matches('/').split('/').collect::<Vec<_>>();
+
+        let route = match parts.as_slice() {
+            [TENANT, "oauth2", "v2.0", "token"] => TOKEN,
+            ["metadata", "identity", "oauth2", "token"] => IDENTITY,
+            ["operations", _] => OPERATION,
+            ["subscriptions", SUBSCRIPTION, "resourceGroups", RESOURCE_GROUP, "providers", "Microsoft.Compute", "virtualMachines", VM, action] => {
+                match *action {
+                    INSTANCE_VIEW => INSTANCE_VIEW,
+                    START => START,
+                    DEALLOCATE => DEALLOCATE,
+                    RUN_COMMAND => RUN_COMMAND,
+                    _ => return Response::error(StatusCode::NOT_FOUND, "ResourceNotFound"),
+                }
+            }
+            _ => return Response::error(StatusCode::NOT_FOUND, "ResourceNotFound"),
+        };
+
+        self.requests.push(format!("{} {}", method, route));
+
+        if route == TOKEN {
+            return self.token(body);
+        }
+
+        if route == IDENTITY {
+            return self.identity(query, headers);
+        }
+
+        let authorized = headers
+            .get("authorization")
+            .and_then(|h| h.strip_prefix("Bearer fake-token-"))
+            .and_then(|n| n.parse::<usize>().ok())
+            .map_or(false, |n| n > self.revoked);
+        if !authorized {
+            return Response::error(StatusCode::UNAUTHORIZED, "InvalidAuthenticationToken");
+        }
+
+        if route != OPERATION && !query.contains("api-version=") {
+            return Response::error(StatusCode::BAD_REQUEST, "MissingApiVersionParameter");
+        }
+
+        if let Some(status) = self.failures.get(route) {
+            return Response::error(*status, "FakeFailure");
+        }
+
+        match (method, route) {
+            ("GET", INSTANCE_VIEW) => self.instance_view(),
+            ("POST", START) => self.begin(Completion::Power("running"), "starting"),
+            ("POST", DEALLOCATE) => self.begin(Completion::Power("deallocated"), "deallocating"),
+            ("POST", RUN_COMMAND) if self.power_state != "running" => {
+                Response::error(StatusCode::CONFLICT, "OperationNotAllowed")
+            }
+            ("POST", RUN_COMMAND) => {
+                let exit = self.script_exit;
+                self.begin(Completion::Script(exit), self.power_state)
+            }
+            ("GET", OPERATION) => self.poll(parts[1].parse().unwrap_or_default()),
+            _ => Response::error(StatusCode::METHOD_NOT_ALLOWED, "MethodNotAllowed"),
+        }
+    }
+
+    fn token(&mut self, body: &str) -> Response {
+        if let Some(status) = self.failures.get(TOKEN) {
+            return Response::json(
+                *status,
+                json!({ "error": "invalid_client", "error_description": "Fake failure" }),
+            );
+        }
+
+        // Tokens have to be requested for the resource manager of the fake
+        let scope = format!(
+            "scope={}",
+            urlencoding::encode(&format!("{}/.default", self.url))
+        );
+        if !body.contains("grant_type=client_credentials")
+            || !(body.contains("client_assertion=") || body.contains("client_secret="))
+            || !body.contains(&scope)
+        {
+            return Response::json(
+                StatusCode::BAD_REQUEST,
+                json!({ "error": "invalid_request" }),
+            );
+        }
+
+        self.tokens += 1;
+
+        Response::json(
+            StatusCode::OK,
+            json!({
+                "token_type": "Bearer",
+                "expires_in": 3600,
+                "access_token": format!("fake-token-{}", self.tokens),
+            }),
+        )
+    }
+
+    /// Token endpoint of the instance metadata service, which expects a resource instead of a scope.
+    fn identity(&mut self, query: &str, headers: &HashMap<String, String>) -> Response {
+        if let Some(status) = self.failures.get(IDENTITY) {
+            return Response::error(*status, "FakeFailure");
+        }
+
+        let resource = format!("resource={}", urlencoding::encode(&self.url));
+        if headers.get("metadata").map(String::as_str) != Some("true")
+            || !query.contains("api-version=")
+            || !query.contains(&resource)
+        {
+            return Response::json(
+                StatusCode::BAD_REQUEST,
+                json!({ "error": "invalid_request" }),
+            );
+        }
+
+        self.tokens += 1;
+
+        Response::json(
+            StatusCode::OK,
+            json!({
+                "token_type": "Bearer",
+                "expires_in": "3599",
+                "resource": self.url,
+                "access_token": format!("fake-token-{}", self.tokens),
+            }),
+        )
+    }
+
+    fn instance_view(&self) -> Response {
+        let agent = if self.power_state == "running" {
+            "Ready"
+        } else {
+            "Not Ready"
+        };
+
+        Response::json(
+            StatusCode::OK,
+            json!({
+                "vmAgent": {
+                    "vmAgentVersion": "2.7.0",
+                    "statuses": [{ "code": "ProvisioningState/succeeded", "displayStatus": agent }],
+                },
+                "statuses": [
+                    {
+                        "code": "ProvisioningState/succeeded",
+                        "displayStatus": "Provisioning succeeded",
+                        "time": Utc::now(),
+                    },
+                    { "code": format!("PowerState/{}", self.power_state) },
+                ],
+            }),
+        )
+    }
+
+    /// Accepts a long running operation, which completes after the configured number of polls.
+    fn begin(&mut self, completion: Completion, power_state: &'static str) -> Response {
+        let id = self.next_operation;
+        self.next_operation += 1;
+        self.power_state = power_state;
+        self.operations.insert(
+            id,
+            Operation {
+                polls: self.polls,
+                completion,
+            },
+        );
+
+        Response {
+            status: StatusCode::ACCEPTED,
+            location: Some(format!("{}/operations/{}", self.url, id)),
+            body: Value::Null,
+        }
+    }
+
+    fn poll(&mut self, id: u64) -> Response {
+        let operation = match self.operations.get_mut(&id) {
+            Some(operation) => operation,
+            None => return Response::error(StatusCode::NOT_FOUND, "OperationNotFound"),
+        };
+
+        if operation.polls > 0 {
+            operation.polls -= 1;
+            return Response {
+                status: StatusCode::ACCEPTED,
+                location: Some(format!("{}/operations/{}", self.url, id)),
+                body: Value::Null,
+            };
+        }
+
+        match self.operations.remove(&id).unwrap().completion {
+            Completion::Power(state) => {
+                self.power_state = state;
+                Response::json(StatusCode::OK, json!({ "status": "Succeeded" }))
+            }
+            Completion::Script(0) => Response::json(
+                StatusCode::OK,
+                json!({ "value": [{
+                    "code": "ProvisioningState/succeeded",
+                    "displayStatus": "Provisioning succeeded",
+                    "message": "Enable succeeded: \n[stdout]\nfake output\n\n[stderr]\n",
+                }] }),
+            ),
+            Completion::Script(exit) => Response::json(
+                StatusCode::OK,
+                json!({ "value": [{
+                    "code": "ProvisioningState/failed",
+                    "displayStatus": "Provisioning failed",
+                    "message": format!(
+                        "Enable failed: failed to execute command: command terminated with exit status={}\n[stdout]\n\n[stderr]\nfake error\n",
+                        exit
+                    ),
+                }] }),
+            ),
+        }
+    }
+}
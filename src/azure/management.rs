@@ -1,3 +1,5 @@
+pub mod network;
+pub mod snapshot;
 pub mod vm;
 pub mod vm_run_cmd;
 
@@ -6,6 +8,8 @@ use crate::azure::AzureClient;
 use crate::{SimpleError, SimpleResult};
 use azure_core::{Body, Response};
 use http::{Request, StatusCode, Uri};
+use serde::de::DeserializeOwned;
+use serde::Deserialize;
 use std::time::{Duration, SystemTime};
 use tokio::time::sleep;
 
@@ -59,6 +63,111 @@ impl<'a> AsyncTask<'a> {
     }
 }
 
+/// Polls a resource until its provisioning state is final, for operations without a location to poll.
+pub struct ProvisioningTask<'a> {
+    client: &'a AzureClient,
+    url: String,
+    timeout: Option<Duration>,
+}
+
+impl<'a> ProvisioningTask<'a> {
+    const POLL_INTERVAL: Duration = Duration::from_secs(3);
+
+    pub fn new(client: &'a AzureClient, url: String) -> Self {
+        Self {
+            client,
+            url,
+            timeout: None,
+        }
+    }
+
+    pub fn timeout(mut self, timeout: Option<Duration>) -> Self {
+        self.timeout = timeout;
+        self
+    }
+
+    /// Returns the resource id, once the provisioning succeeded.
+    pub async fn wait(self) -> SimpleResult<String> {
+        #[derive(Deserialize)]
+        struct Resource {
+            id: String,
+            properties: Properties,
+        }
+
+        #[derive(Deserialize)]
+        struct Properties {
+            #[serde(rename = "provisioningState", default)]
+            provisioning_state: Option<String>,
+        }
+
+        let begin = SystemTime::now();
+
+        loop {
+            let resource: Resource = get_json(self.client, self.url.clone()).await?;
+
+            // Without a state the provisioning is still pending, until the timeout
+            match resource.properties.provisioning_state.as_deref() {
+                Some("Succeeded") => return Ok(resource.id),
+                Some(state @ ("Failed" | "Canceled")) => {
+                    return Err(SimpleError::ProvisioningFailed(state.to_owned()))
+                }
+                Some(_) | None => {}
+            }
+
+            if let Some(timeout) = self.timeout {
+                if SystemTime::now().duration_since(begin).unwrap() > timeout {
+                    return Err(SimpleError::Timeout);
+                }
+            }
+
+            sleep(Self::POLL_INTERVAL).await;
+        }
+    }
+}
+
+/// Uri to poll for the result of an accepted request.
+fn location(response: &Response) -> SimpleResult<Uri> {
+    let location = response
+        .headers()
+        .get("location")
+        .ok_or_else(|| SimpleError::UnexpectedResponse("no location to poll".to_owned()))?;
+
+    Uri::try_from(location.to_str()?)
+        .map_err(|why| SimpleError::UnexpectedResponse(format!("invalid location: {}", why)))
+}
+
+/// Follows the next links of a paged list.
+async fn get_all<T: DeserializeOwned>(client: &AzureClient, url: String) -> SimpleResult<Vec<T>> {
+    #[derive(Deserialize)]
+    struct Page<T> {
+        value: Vec<T>,
+        #[serde(rename = "nextLink", default)]
+        next_link: Option<String>,
+    }
+
+    let mut url = Some(url);
+    let mut items = Vec::new();
+
+    while let Some(next) = url {
+        let page: Page<T> = get_json(client, next).await?;
+        items.extend(page.value);
+        url = page.next_link;
+    }
+
+    Ok(items)
+}
+
+async fn get_json<T: DeserializeOwned>(client: &AzureClient, url: String) -> SimpleResult<T> {
+    let request = Request::get(url)
+        .body(Default::default())
+        .expect("Error creating request.")
+        .into();
+
+    let response = send_request(client, request).await?;
+    let body = response.into_body_string().await;
+    serde_json::from_str(&body).map_err(Into::into)
+}
+
 async fn send_request(
     client: &AzureClient,
     mut request: azure_core::Request,
@@ -84,8 +193,8 @@ fn content_length(request: &azure_core::Request) -> Option<usize> {
 }
 
 macro_rules! api_ {
-    () => {
-        "https://management.azure.com"
+    ($client:expr) => {
+        $client.endpoints.resource_manager.as_str()
     };
 }
 
@@ -100,9 +209,9 @@ macro_rules! uri_ {
 use uri_ as uri;
 
 macro_rules! base_ {
-    ($sub:expr, $rg:expr, $($part:expr),*) => {
+    ($client:expr, $sub:expr, $rg:expr, $($part:expr),*) => {
         $crate::azure::management::uri![
-            $crate::azure::management::api!(),
+            $crate::azure::management::api!($client),
             "subscriptions",
             $sub,
             "resourceGroups",
@@ -115,8 +224,9 @@ macro_rules! base_ {
 use base_ as base;
 
 macro_rules! compute_ {
-    ($sub:expr, $rg:expr, $($part:expr),*) => {
+    ($client:expr, $sub:expr, $rg:expr, $($part:expr),*) => {
         $crate::azure::management::base!(
+            $client,
             $sub,
             $rg,
             "providers/Microsoft.Compute",
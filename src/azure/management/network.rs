@@ -0,0 +1,127 @@
+use crate::azure::management::{api, api_version, compute, get_json};
+use crate::azure::{AzureClient, AzureName, SubscriptionId};
+use crate::SimpleResult;
+use async_trait::async_trait;
+use serde::Deserialize;
+
+const COMPUTE_API_VERSION: &str = "2021-11-01";
+const NETWORK_API_VERSION: &str = "2021-08-01";
+
+#[async_trait]
+pub trait NetworkClient {
+    /// Public addresses of all network interfaces of the vm, empty while it is deallocated.
+    async fn public_addresses(
+        &self,
+        subscription: &SubscriptionId,
+        rg: &AzureName,
+        vm: &AzureName,
+    ) -> SimpleResult<Vec<PublicAddress>>;
+}
+
+#[async_trait]
+impl NetworkClient for AzureClient {
+    async fn public_addresses(
+        &self,
+        subscription: &SubscriptionId,
+        rg: &AzureName,
+        vm: &AzureName,
+    ) -> SimpleResult<Vec<PublicAddress>> {
+        let url = compute!(self, subscription, rg, "virtualMachines", vm)
+            + &api_version!(COMPUTE_API_VERSION);
+        let vm: Resource<VmProperties> = get_json(self, url).await?;
+
+        let mut addresses = Vec::new();
+        for nic in vm.properties.network_profile.network_interfaces {
+            let url = api!(self).to_owned() + &nic.id + &api_version!(NETWORK_API_VERSION);
+            let nic: Resource<NicProperties> = get_json(self, url).await?;
+
+            let ip_ids = nic
+                .properties
+                .ip_configurations
+                .into_iter()
+                .filter_map(|c| c.properties.public_ip_address);
+
+            for ip in ip_ids {
+                let url = api!(self).to_owned() + &ip.id + &api_version!(NETWORK_API_VERSION);
+                let ip: Resource<PublicIpProperties> = get_json(self, url).await?;
+
+                // Dynamic addresses are only assigned while the vm is running
+                if let Some(address) = ip.properties.ip_address {
+                    addresses.push(PublicAddress {
+                        ip: address,
+                        fqdn: ip.properties.dns_settings.and_then(|dns| dns.fqdn),
+                    });
+                }
+            }
+        }
+
+        Ok(addresses)
+    }
+}
+
+#[derive(Debug, Clone)]
+pub struct PublicAddress {
+    pub ip: String,
+    pub fqdn: Option<String>,
+}
+
+impl PublicAddress {
+    /// Replaces `{ip}` and `{fqdn}` in the template, the fqdn falls back to the ip.
+    pub fn format(&self, template: &str) -> String {
+        template
+            .replace("{ip}", &self.ip)
+            .replace("{fqdn}", self.fqdn.as_deref().unwrap_or(&self.ip))
+    }
+}
+
+#[derive(Debug, Clone, Deserialize)]
+struct Resource<P> {
+    properties: P,
+}
+
+#[derive(Debug, Clone, Deserialize)]
+struct SubResource {
+    id: String,
+}
+
+#[derive(Debug, Clone, Deserialize)]
+#[serde(rename_all = "camelCase")]
+struct VmProperties {
+    network_profile: NetworkProfile,
+}
+
+#[derive(Debug, Clone, Deserialize)]
+#[serde(rename_all = "camelCase")]
+struct NetworkProfile {
+    #[serde(default)]
+    network_interfaces: Vec<SubResource>,
+}
+
+#[derive(Debug, Clone, Deserialize)]
+#[serde(rename_all = "camelCase")]
+struct NicProperties {
+    #[serde(default)]
+    ip_configurations: Vec<Resource<IpConfigurationProperties>>,
+}
+
+#[derive(Debug, Clone, Deserialize)]
+#[serde(rename_all = "camelCase")]
+struct IpConfigurationProperties {
+    #[serde(rename = "publicIPAddress", default)]
+    public_ip_address: Option<SubResource>,
+}
+
+#[derive(Debug, Clone, Deserialize)]
+#[serde(rename_all = "camelCase")]
+struct PublicIpProperties {
+    #[serde(default)]
+    ip_address: Option<String>,
+    #[serde(default)]
+    dns_settings: Option<DnsSettings>,
+}
+
+#[derive(Debug, Clone, Deserialize)]
+struct DnsSettings {
+    #[serde(default)]
+    fqdn: Option<String>,
+}
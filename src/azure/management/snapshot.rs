@@ -0,0 +1,193 @@
+use crate::azure::management::{
+    api, api_version, compute, get_all, get_json, location, send_request, AsyncTask,
+    ProvisioningTask,
+};
+use crate::azure::{AzureClient, AzureName, SubscriptionId};
+use crate::SimpleResult;
+use async_trait::async_trait;
+use chrono::{DateTime, Utc};
+use http::{Request, StatusCode};
+use serde::Deserialize;
+use serde_json::json;
+use std::collections::HashMap;
+
+const API_VERSION: &str = "2021-12-01";
+
+#[async_trait]
+pub trait SnapshotClient {
+    /// Snapshots the managed disk with the given resource id into the resource group.
+    async fn create_snapshot(
+        &self,
+        subscription: &SubscriptionId,
+        rg: &AzureName,
+        name: &str,
+        location: &str,
+        disk_id: &str,
+        tags: &HashMap<String, String>,
+    ) -> SimpleResult<ProvisioningTask<'_>>;
+    async fn snapshots(
+        &self,
+        subscription: &SubscriptionId,
+        rg: &AzureName,
+    ) -> SimpleResult<Vec<Snapshot>>;
+    async fn delete_snapshot(
+        &self,
+        subscription: &SubscriptionId,
+        rg: &AzureName,
+        name: &str,
+    ) -> SimpleResult<()>;
+    async fn disk(&self, disk_id: &str) -> SimpleResult<Disk>;
+    /// Creates a managed disk from the snapshot, with the sku and zones of the given disk.
+    async fn create_disk(
+        &self,
+        subscription: &SubscriptionId,
+        rg: &AzureName,
+        name: &str,
+        like: &Disk,
+        snapshot_id: &str,
+    ) -> SimpleResult<ProvisioningTask<'_>>;
+}
+
+#[async_trait]
+impl SnapshotClient for AzureClient {
+    async fn create_snapshot(
+        &self,
+        subscription: &SubscriptionId,
+        rg: &AzureName,
+        name: &str,
+        location: &str,
+        disk_id: &str,
+        tags: &HashMap<String, String>,
+    ) -> SimpleResult<ProvisioningTask<'_>> {
+        let url = compute!(self, subscription, rg, "snapshots", name) + &api_version!(API_VERSION);
+
+        // Incremental snapshots only store the changes since the last one
+        let body = json!({
+            "location": location,
+            "tags": tags,
+            "properties": {
+                "creationData": {
+                    "createOption": "Copy",
+                    "sourceResourceId": disk_id,
+                },
+                "incremental": true,
+            },
+        });
+
+        put(self, url, body).await
+    }
+
+    async fn snapshots(
+        &self,
+        subscription: &SubscriptionId,
+        rg: &AzureName,
+    ) -> SimpleResult<Vec<Snapshot>> {
+        get_all(
+            self,
+            compute!(self, subscription, rg, "snapshots") + &api_version!(API_VERSION),
+        )
+        .await
+    }
+
+    async fn delete_snapshot(
+        &self,
+        subscription: &SubscriptionId,
+        rg: &AzureName,
+        name: &str,
+    ) -> SimpleResult<()> {
+        let url = compute!(self, subscription, rg, "snapshots", name) + &api_version!(API_VERSION);
+
+        let request = Request::delete(url)
+            .body(Default::default())
+            .expect("Error creating request.")
+            .into();
+
+        let response = send_request(self, request).await?;
+
+        if response.status() == StatusCode::ACCEPTED {
+            AsyncTask::new(self, location(&response)?).wait().await?;
+        }
+
+        Ok(())
+    }
+
+    async fn disk(&self, disk_id: &str) -> SimpleResult<Disk> {
+        get_json(
+            self,
+            api!(self).to_owned() + disk_id + &api_version!(API_VERSION),
+        )
+        .await
+    }
+
+    async fn create_disk(
+        &self,
+        subscription: &SubscriptionId,
+        rg: &AzureName,
+        name: &str,
+        like: &Disk,
+        snapshot_id: &str,
+    ) -> SimpleResult<ProvisioningTask<'_>> {
+        let url = compute!(self, subscription, rg, "disks", name) + &api_version!(API_VERSION);
+
+        let mut body = json!({
+            "location": like.location,
+            "properties": {
+                "creationData": {
+                    "createOption": "Copy",
+                    "sourceResourceId": snapshot_id,
+                },
+            },
+        });
+
+        if let Some(ref sku) = like.sku {
+            body["sku"] = sku.clone();
+        }
+        if !like.zones.is_empty() {
+            body["zones"] = json!(like.zones);
+        }
+
+        put(self, url, body).await
+    }
+}
+
+async fn put(
+    client: &AzureClient,
+    url: String,
+    body: serde_json::Value,
+) -> SimpleResult<ProvisioningTask<'_>> {
+    let request = Request::put(&url)
+        .header("Content-Type", "application/json")
+        .body(body.to_string().into_bytes().into())
+        .expect("Error creating request.")
+        .into();
+
+    send_request(client, request).await?;
+
+    Ok(ProvisioningTask::new(client, url))
+}
+
+#[derive(Debug, Clone, Deserialize)]
+pub struct Snapshot {
+    pub id: String,
+    pub name: String,
+    #[serde(default)]
+    pub tags: HashMap<String, String>,
+    pub properties: SnapshotProperties,
+}
+
+#[derive(Debug, Clone, Deserialize)]
+pub struct SnapshotProperties {
+    #[serde(rename = "timeCreated", default)]
+    pub time_created: Option<DateTime<Utc>>,
+    #[serde(rename = "diskSizeGB", default)]
+    pub disk_size_gb: Option<u64>,
+}
+
+#[derive(Debug, Clone, Deserialize)]
+pub struct Disk {
+    pub location: String,
+    #[serde(default)]
+    pub sku: Option<serde_json::Value>,
+    #[serde(default)]
+    pub zones: Vec<String>,
+}
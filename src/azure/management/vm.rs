@@ -1,16 +1,23 @@
-use crate::azure::management::{api_version, send_request, AsyncTask};
+use crate::azure::management::{
+    api, api_version, compute, get_all, send_request, uri, AsyncTask, ProvisioningTask,
+};
 use crate::azure::{AzureClient, AzureName, SubscriptionId};
 use crate::SimpleResult;
 use async_trait::async_trait;
+use chrono::{DateTime, Utc};
 use http::Request;
-use serde::Deserialize;
+use serde::{Deserialize, Serialize};
+use serde_json::json;
+use std::collections::HashMap;
+use std::fmt::{Display, Formatter};
 use std::time::Duration;
 
 const API_VERSION: &str = "2021-11-01";
 
 macro_rules! vm_ {
-    ($sub:expr, $rg:expr, $vm:expr, $($part:expr),*) => {
+    ($client:expr, $sub:expr, $rg:expr, $vm:expr, $($part:expr),*) => {
         $crate::azure::management::compute!(
+            $client,
             $sub,
             $rg,
             "virtualMachines",
@@ -36,12 +43,46 @@ pub trait VmClient {
         rg: &AzureName,
         name: &AzureName,
     ) -> SimpleResult<ActionTask<'_>>;
+    async fn restart(
+        &self,
+        subscription: &SubscriptionId,
+        rg: &AzureName,
+        name: &AzureName,
+    ) -> SimpleResult<ActionTask<'_>>;
     async fn instance_view(
         &self,
         subscription: &SubscriptionId,
         rg: &AzureName,
         name: &AzureName,
     ) -> SimpleResult<InstanceView>;
+    async fn model(
+        &self,
+        subscription: &SubscriptionId,
+        rg: &AzureName,
+        name: &AzureName,
+    ) -> SimpleResult<VirtualMachine>;
+    /// Vms of the resource group, or of the whole subscription.
+    async fn list(
+        &self,
+        subscription: &SubscriptionId,
+        rg: Option<&AzureName>,
+    ) -> SimpleResult<Vec<VmResource>>;
+    /// Changes the vm size, the returned task waits for the update to finish.
+    async fn resize(
+        &self,
+        subscription: &SubscriptionId,
+        rg: &AzureName,
+        name: &AzureName,
+        size: &str,
+    ) -> SimpleResult<ProvisioningTask<'_>>;
+    /// Attaches other managed disks, the vm has to be deallocated.
+    async fn swap_disks(
+        &self,
+        subscription: &SubscriptionId,
+        rg: &AzureName,
+        name: &AzureName,
+        disks: &StorageProfile,
+    ) -> SimpleResult<ProvisioningTask<'_>>;
 }
 
 #[async_trait]
@@ -52,90 +93,373 @@ impl VmClient for AzureClient {
         rg: &AzureName,
         name: &AzureName,
     ) -> SimpleResult<ActionTask<'_>> {
-        let url = vm!(subscription, rg, name, "start") + &api_version!(API_VERSION);
+        action(self, subscription, rg, name, "start").await
+    }
+
+    async fn deallocate(
+        &self,
+        subscription: &SubscriptionId,
+        rg: &AzureName,
+        name: &AzureName,
+    ) -> SimpleResult<ActionTask<'_>> {
+        action(self, subscription, rg, name, "deallocate").await
+    }
 
-        let request = Request::post(url)
+    async fn restart(
+        &self,
+        subscription: &SubscriptionId,
+        rg: &AzureName,
+        name: &AzureName,
+    ) -> SimpleResult<ActionTask<'_>> {
+        action(self, subscription, rg, name, "restart").await
+    }
+
+    async fn instance_view(
+        &self,
+        subscription: &SubscriptionId,
+        rg: &AzureName,
+        name: &AzureName,
+    ) -> SimpleResult<InstanceView> {
+        let url = vm!(self, subscription, rg, name, "instanceView") + &api_version!(API_VERSION);
+
+        let request = Request::get(url)
             .body(Default::default())
-            .expect("Failed building http request.")
+            .expect("Error creating request.")
             .into();
 
         let response = send_request(self, request).await?;
-
-        let task = ActionTask {
-            task: AsyncTask::new(
-                self,
-                response.headers()["location"]
-                    .to_str()?
-                    .try_into()
-                    .expect("Not a valid uri."),
-            ),
-        };
-
-        Ok(task)
+        let body = response.into_body_string().await;
+        serde_json::from_str(&body).map_err(Into::into)
     }
 
-    async fn deallocate(
+    async fn model(
         &self,
         subscription: &SubscriptionId,
         rg: &AzureName,
         name: &AzureName,
-    ) -> SimpleResult<ActionTask<'_>> {
-        let url = vm!(subscription, rg, name, "deallocate") + &api_version!(API_VERSION);
+    ) -> SimpleResult<VirtualMachine> {
+        let url =
+            compute!(self, subscription, rg, "virtualMachines", name) + &api_version!(API_VERSION);
 
-        let request = Request::post(url)
+        let request = Request::get(url)
             .body(Default::default())
-            .expect("Failed building http request.")
+            .expect("Error creating request.")
             .into();
 
         let response = send_request(self, request).await?;
+        let body = response.into_body_string().await;
+        serde_json::from_str(&body).map_err(Into::into)
+    }
 
-        let task = ActionTask {
-            task: AsyncTask::new(
-                self,
-                response.headers()["location"]
-                    .to_str()?
-                    .try_into()
-                    .expect("Not a valid uri."),
-            ),
+    async fn list(
+        &self,
+        subscription: &SubscriptionId,
+        rg: Option<&AzureName>,
+    ) -> SimpleResult<Vec<VmResource>> {
+        let url = match rg {
+            Some(rg) => compute!(self, subscription, rg, "virtualMachines"),
+            None => uri![
+                api!(self),
+                "subscriptions",
+                subscription,
+                "providers/Microsoft.Compute/virtualMachines"
+            ],
         };
 
-        Ok(task)
+        get_all(self, url + &api_version!(API_VERSION)).await
     }
 
-    async fn instance_view(
+    async fn resize(
         &self,
         subscription: &SubscriptionId,
         rg: &AzureName,
         name: &AzureName,
-    ) -> SimpleResult<InstanceView> {
-        let url = vm!(subscription, rg, name, "instanceView") + &api_version!(API_VERSION);
+        size: &str,
+    ) -> SimpleResult<ProvisioningTask<'_>> {
+        let url =
+            compute!(self, subscription, rg, "virtualMachines", name) + &api_version!(API_VERSION);
 
-        let request = Request::get(url)
-            .body(Default::default())
+        let body = json!({
+            "properties": {
+                "hardwareProfile": {
+                    "vmSize": size,
+                },
+            },
+        });
+
+        let request = Request::patch(&url)
+            .header("Content-Type", "application/json")
+            .body(body.to_string().into_bytes().into())
             .expect("Error creating request.")
             .into();
 
-        let response = send_request(self, request).await?;
-        let body = response.into_body_string().await;
-        serde_json::from_str(&body).map_err(Into::into)
+        send_request(self, request).await?;
+
+        Ok(ProvisioningTask::new(self, url))
+    }
+
+    async fn swap_disks(
+        &self,
+        subscription: &SubscriptionId,
+        rg: &AzureName,
+        name: &AzureName,
+        disks: &StorageProfile,
+    ) -> SimpleResult<ProvisioningTask<'_>> {
+        let url =
+            compute!(self, subscription, rg, "virtualMachines", name) + &api_version!(API_VERSION);
+
+        let body = json!({
+            "properties": {
+                "storageProfile": disks,
+            },
+        });
+
+        let request = Request::patch(&url)
+            .header("Content-Type", "application/json")
+            .body(body.to_string().into_bytes().into())
+            .expect("Error creating request.")
+            .into();
+
+        send_request(self, request).await?;
+
+        Ok(ProvisioningTask::new(self, url))
+    }
+}
+
+async fn action<'a>(
+    client: &'a AzureClient,
+    subscription: &SubscriptionId,
+    rg: &AzureName,
+    name: &AzureName,
+    action: &str,
+) -> SimpleResult<ActionTask<'a>> {
+    let url = vm!(client, subscription, rg, name, action) + &api_version!(API_VERSION);
+
+    let request = Request::post(url)
+        .body(Default::default())
+        .expect("Failed building http request.")
+        .into();
+
+    let response = send_request(client, request).await?;
+
+    let task = ActionTask {
+        task: AsyncTask::new(
+            client,
+            response.headers()["location"]
+                .to_str()?
+                .try_into()
+                .expect("Not a valid uri."),
+        ),
+    };
+
+    Ok(task)
+}
+
+/// Entry of a vm list.
+#[derive(Debug, Clone, Deserialize)]
+pub struct VmResource {
+    pub id: String,
+    pub name: String,
+    #[serde(default)]
+    pub tags: HashMap<String, String>,
+}
+
+impl VmResource {
+    pub fn resource_group(&self) -> Option<&str> {
+        let mut parts = self.id.split('/');
+        parts.find(|p| p.eq_ignore_ascii_case("resourceGroups"))?;
+        parts.next()
     }
 }
 
+#[derive(Debug, Clone, Deserialize)]
+pub struct VirtualMachine {
+    #[serde(default)]
+    pub location: String,
+    pub properties: VmProperties,
+}
+
+impl VirtualMachine {
+    pub fn size(&self) -> &str {
+        &self.properties.hardware_profile.vm_size
+    }
+}
+
+#[derive(Debug, Clone, Deserialize)]
+pub struct VmProperties {
+    #[serde(rename = "hardwareProfile")]
+    pub hardware_profile: HardwareProfile,
+    #[serde(rename = "storageProfile", default)]
+    pub storage_profile: Option<StorageProfile>,
+}
+
+#[derive(Debug, Clone, Deserialize)]
+pub struct HardwareProfile {
+    #[serde(rename = "vmSize")]
+    pub vm_size: String,
+}
+
+#[derive(Debug, Clone, Deserialize, Serialize)]
+pub struct StorageProfile {
+    #[serde(rename = "osDisk")]
+    pub os_disk: AttachedDisk,
+    #[serde(rename = "dataDisks", default)]
+    pub data_disks: Vec<AttachedDisk>,
+}
+
+impl StorageProfile {
+    /// Managed disks by slot, `os` for the os disk and `lun<n>` for data disks.
+    pub fn managed_disks(&self) -> Vec<(String, &AttachedDisk)> {
+        let mut disks = vec![("os".to_owned(), &self.os_disk)];
+        disks.extend(
+            self.data_disks
+                .iter()
+                .filter_map(|d| d.lun.map(|lun| (format!("lun{}", lun), d))),
+        );
+
+        disks
+            .into_iter()
+            .filter(|(_, d)| d.managed_disk.is_some())
+            .collect()
+    }
+
+    pub fn disk_mut(&mut self, slot: &str) -> Option<&mut AttachedDisk> {
+        if slot == "os" {
+            return Some(&mut self.os_disk);
+        }
+
+        let lun = slot.strip_prefix("lun")?.parse().ok()?;
+        self.data_disks.iter_mut().find(|d| d.lun == Some(lun))
+    }
+}
+
+#[derive(Debug, Clone, Deserialize, Serialize)]
+pub struct AttachedDisk {
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub lun: Option<i32>,
+    pub name: String,
+    #[serde(
+        rename = "createOption",
+        default,
+        skip_serializing_if = "Option::is_none"
+    )]
+    pub create_option: Option<String>,
+    #[serde(rename = "managedDisk", default)]
+    pub managed_disk: Option<ManagedDisk>,
+}
+
+#[derive(Debug, Clone, Deserialize, Serialize)]
+pub struct ManagedDisk {
+    pub id: String,
+}
+
 #[derive(Debug, Clone, Deserialize)]
 pub struct InstanceView {
+    #[serde(rename = "osName", default)]
+    pub os_name: Option<String>,
+    #[serde(rename = "osVersion", default)]
+    pub os_version: Option<String>,
     #[serde(rename = "vmAgent", default)]
     pub vm_agent: Option<VmAgent>,
+    #[serde(default)]
+    pub statuses: Vec<Status>,
+}
+
+impl InstanceView {
+    pub fn power_state(&self) -> PowerState {
+        self.statuses
+            .iter()
+            .find_map(|s| s.code.strip_prefix("PowerState/"))
+            .map_or(PowerState::Unknown, Into::into)
+    }
+
+    pub fn provisioning_state(&self) -> Option<&Status> {
+        self.statuses
+            .iter()
+            .find(|s| s.code.starts_with("ProvisioningState/"))
+    }
+
+    /// Time since the last successful provisioning operation, which is the boot for a running vm.
+    pub fn uptime(&self) -> Option<chrono::Duration> {
+        if self.power_state() != PowerState::Running {
+            return None;
+        }
+
+        self.provisioning_state()
+            .and_then(|s| s.time)
+            .map(|t| Utc::now().signed_duration_since(t))
+    }
+
+    pub fn agent_ready(&self) -> bool {
+        self.agent_status()
+            .map_or(false, |s| s.display_status == "Ready")
+    }
+
+    pub fn agent_status(&self) -> Option<&Status> {
+        self.vm_agent
+            .as_ref()
+            .and_then(|agent| agent.statuses.get(0))
+    }
 }
 
 #[derive(Debug, Clone, Deserialize)]
 pub struct VmAgent {
+    #[serde(rename = "vmAgentVersion", default)]
+    pub vm_agent_version: Option<String>,
+    #[serde(default)]
     pub statuses: Vec<Status>,
 }
 
 #[derive(Debug, Clone, Deserialize)]
 pub struct Status {
-    #[serde(rename = "displayStatus")]
+    pub code: String,
+    #[serde(rename = "displayStatus", default)]
     pub display_status: String,
+    #[serde(default)]
+    pub message: Option<String>,
+    #[serde(default)]
+    pub time: Option<DateTime<Utc>>,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum PowerState {
+    Starting,
+    Running,
+    Stopping,
+    Stopped,
+    Deallocating,
+    Deallocated,
+    Unknown,
+}
+
+impl From<&str> for PowerState {
+    fn from(s: &str) -> Self {
+        match s {
+            "starting" => PowerState::Starting,
+            "running" => PowerState::Running,
+            "stopping" => PowerState::Stopping,
+            "stopped" => PowerState::Stopped,
+            "deallocating" => PowerState::Deallocating,
+            "deallocated" => PowerState::Deallocated,
+            _ => PowerState::Unknown,
+        }
+    }
+}
+
+impl Display for PowerState {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        let s = match self {
+            PowerState::Starting => "starting",
+            PowerState::Running => "running",
+            PowerState::Stopping => "stopping",
+            PowerState::Stopped => "stopped",
+            PowerState::Deallocating => "deallocating",
+            PowerState::Deallocated => "deallocated",
+            PowerState::Unknown => "unknown",
+        };
+
+        s.fmt(f)
+    }
 }
 
 pub struct ActionTask<'a> {
@@ -1,27 +1,28 @@
-use crate::azure::management::vm::vm;
-use crate::azure::management::{api_version, send_request, AsyncTask};
+use crate::azure::management::vm::{vm, Status};
+use crate::azure::management::{api_version, location, send_request, AsyncTask};
 use crate::azure::{AzureClient, AzureName, SubscriptionId};
-use crate::SimpleResult;
+use crate::{SimpleError, SimpleResult};
 use async_trait::async_trait;
 use http::{Request, StatusCode, Uri};
 use serde::ser::SerializeSeq;
 use serde::{Serialize, Serializer};
 use serde_json::{json, Value};
 use std::cell::RefCell;
+use std::fmt::{Display, Formatter};
 use std::marker::PhantomData;
 use std::time::Duration;
 
 const API_VERSION: &str = "2021-07-01";
 
 macro_rules! run_command {
-    ($sub:expr, $rg:expr, $vm:expr) => {
-        vm!($sub, $rg, $vm, "runCommand")
+    ($client:expr, $sub:expr, $rg:expr, $vm:expr) => {
+        vm!($client, $sub, $rg, $vm, "runCommand")
     };
 }
 
 #[async_trait]
 pub trait VmRunCmdClient {
-    async fn run<C, P, S, U, V>(
+    async fn run<C, P, S, V>(
         &self,
         subscription: &SubscriptionId,
         rg: &AzureName,
@@ -30,15 +31,14 @@ pub trait VmRunCmdClient {
     ) -> SimpleResult<CommandTask<'_, C>>
     where
         C: Into<BaseCommand<P, S>> + Command + Send,
-        P: AsRef<[U]> + Send,
-        U: AsRef<str> + Sync,
+        P: AsRef<[RunCommandParameter]> + Send,
         S: AsRef<[V]> + Send,
         V: AsRef<str> + Sync;
 }
 
 #[async_trait]
 impl VmRunCmdClient for AzureClient {
-    async fn run<C, P, S, U, V>(
+    async fn run<C, P, S, V>(
         &self,
         subscription: &SubscriptionId,
         rg: &AzureName,
@@ -47,15 +47,14 @@ impl VmRunCmdClient for AzureClient {
     ) -> SimpleResult<CommandTask<'_, C>>
     where
         C: Into<BaseCommand<P, S>> + Command + Send,
-        P: AsRef<[U]> + Send,
-        U: AsRef<str> + Sync,
+        P: AsRef<[RunCommandParameter]> + Send,
         S: AsRef<[V]> + Send,
         V: AsRef<str> + Sync,
     {
-        let url: String = run_command!(subscription, rg, vm) + &api_version!(API_VERSION);
+        let url: String = run_command!(self, subscription, rg, vm) + &api_version!(API_VERSION);
         let cmd = cmd.into();
 
-        let parameters = cmd.parameters.as_ref().iter().map(|p| p.as_ref());
+        let parameters = cmd.parameters.as_ref().iter();
         let script = cmd.script.as_ref().iter().map(|s| s.as_ref());
 
         struct IteratorAdapter<I> {
@@ -107,17 +106,17 @@ impl VmRunCmdClient for AzureClient {
                         _c: PhantomData,
                     })
                 } else if r.status() == StatusCode::ACCEPTED {
-                    let location = r.headers()["location"].to_str()?;
                     Ok(CommandTask {
-                        resp_type: ResponseType::Status202(
-                            location.try_into().expect("Not a valid uri."),
-                        ),
+                        resp_type: ResponseType::Status202(location(&r)?),
                         client: self,
                         timeout: None,
                         _c: PhantomData,
                     })
                 } else {
-                    unimplemented!("Received unexpected status code: {}.", r.status())
+                    Err(SimpleError::UnexpectedResponse(format!(
+                        "status code {}",
+                        r.status()
+                    )))
                 }
             }
             Err(e) => Err(e),
@@ -138,30 +137,127 @@ pub struct BaseCommand<P, S> {
     pub script: S,
 }
 
+/// Parameter of a run command, shell scripts receive them as environment variables.
+#[derive(Debug, Clone, Serialize)]
+pub struct RunCommandParameter {
+    pub name: String,
+    pub value: String,
+}
+
 #[derive(Debug, Clone)]
 pub struct ShellCommand<S> {
     pub script: S,
+    pub parameters: Vec<RunCommandParameter>,
 }
 
-impl<S> From<ShellCommand<S>> for BaseCommand<[&'static str; 0], S> {
-    fn from(cmd: ShellCommand<S>) -> BaseCommand<[&'static str; 0], S> {
+impl<S> From<ShellCommand<S>> for BaseCommand<Vec<RunCommandParameter>, S> {
+    fn from(cmd: ShellCommand<S>) -> BaseCommand<Vec<RunCommandParameter>, S> {
         BaseCommand {
             command_id: "RunShellScript",
-            parameters: [],
+            parameters: cmd.parameters,
             script: cmd.script,
         }
     }
 }
 
 impl<S> Command for ShellCommand<S> {
-    type Output = String;
+    type Output = ScriptOutput;
 
-    fn parse_response(body: impl AsRef<str>) -> SimpleResult<String> {
+    fn parse_response(body: impl AsRef<str>) -> SimpleResult<ScriptOutput> {
         let value: Value = serde_json::from_str(body.as_ref())?;
-        let msg = value["value"][0]["message"]
-            .as_str()
-            .expect("Couldn't parse response.");
-        Ok(msg.to_owned())
+
+        // The result of an async operation wraps the statuses into its properties
+        let statuses = match value.get("value") {
+            Some(statuses) => statuses,
+            None => &value["properties"]["output"]["value"],
+        };
+        let statuses: Vec<Status> = serde_json::from_value(statuses.clone())?;
+
+        let mut output = ScriptOutput::default();
+
+        for status in statuses {
+            let message = status.message.unwrap_or_default();
+            let mut code = status.code.split('/');
+
+            // Shell scripts report both streams in the message of the provisioning state
+            if let (Some("ProvisioningState"), Some(state)) = (code.next(), code.next()) {
+                let (head, streams) = message
+                    .split_once("[stdout]\n")
+                    .unwrap_or((message.as_str(), ""));
+                let (stdout, stderr) = streams.split_once("\n[stderr]\n").unwrap_or((streams, ""));
+
+                output.stdout = stdout.to_owned();
+                output.stderr = stderr.to_owned();
+
+                if state == "failed" {
+                    output.exit_status = exit_status(head);
+                    if output.exit_status.is_none() {
+                        output.error = Some(head.trim().to_owned());
+                    }
+                }
+            }
+        }
+
+        Ok(output)
+    }
+}
+
+fn exit_status(message: &str) -> Option<i32> {
+    let (_, status) = message.split_once("exit status=")?;
+    let digits = status
+        .chars()
+        .take_while(|c| c.is_ascii_digit() || *c == '-')
+        .collect::<String>();
+    digits.parse().ok()
+}
+
+/// Output of a shell script, parsed from the statuses of the run command.
+#[derive(Debug, Clone, Default)]
+pub struct ScriptOutput {
+    pub stdout: String,
+    pub stderr: String,
+    /// Only reported, if the script failed.
+    pub exit_status: Option<i32>,
+    /// Error of the run command extension, e.g. if the script couldn't be executed at all.
+    pub error: Option<String>,
+}
+
+impl ScriptOutput {
+    pub fn succeeded(&self) -> bool {
+        self.error.is_none() && self.exit_status.map_or(true, |s| s == 0)
+    }
+
+    /// Turns a failed script into a [`SimpleError::ScriptFailed`].
+    pub fn into_result(self) -> SimpleResult<ScriptOutput> {
+        if self.succeeded() {
+            Ok(self)
+        } else {
+            Err(SimpleError::ScriptFailed(Box::new(self)))
+        }
+    }
+
+    pub fn summary(&self) -> String {
+        match (&self.error, self.exit_status) {
+            (Some(error), _) => format!("Script failed: {}", error),
+            (None, Some(status)) if status != 0 => {
+                format!("Script failed with exit status {}", status)
+            }
+            _ => "Script succeeded".to_owned(),
+        }
+    }
+}
+
+impl Display for ScriptOutput {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        let stdout = self.stdout.trim();
+        let stderr = self.stderr.trim();
+
+        match (stdout.is_empty(), stderr.is_empty()) {
+            (true, true) => Ok(()),
+            (false, true) => stdout.fmt(f),
+            (true, false) => write!(f, "[stderr]\n{}", stderr),
+            (false, false) => write!(f, "{}\n[stderr]\n{}", stdout, stderr),
+        }
     }
 }
 
@@ -0,0 +1,165 @@
+use crate::command::usage::{current_month, next_month, run_hours, sync_runs};
+use crate::command::{progress, InstanceLockKey, ProgressMessage};
+use crate::conf::{BudgetConfig, ConfigKey, ServerConfig};
+use crate::dependency::stop_with_dependencies;
+use crate::sql::usage::{is_open, runs_between};
+use crate::sql::{Sql, SqlKey};
+use crate::{AzureClientKey, SimpleResult};
+use chrono::{NaiveDate, Utc};
+use log::{info, warn};
+use serenity::client::Context;
+use serenity::model::id::ChannelId;
+use std::collections::HashSet;
+use std::sync::Arc;
+use std::time::Duration;
+use tokio::time::sleep;
+
+const BUDGET_CHECK_INTERVAL: Duration = Duration::from_secs(300);
+
+// Share of the budget, after which running servers get a warning
+const WARNING_THRESHOLD: f64 = 0.9;
+
+/// Hours the server ran in the given month.
+pub fn used_hours(sql: &Sql, s_name: &str, month: NaiveDate) -> SimpleResult<f64> {
+    let from = month.and_hms_opt(0, 0, 0).unwrap();
+    let to = next_month(month).and_hms_opt(0, 0, 0).unwrap();
+    let now = Utc::now().naive_utc();
+
+    let runs = runs_between(&mut sql.connection.get()?, from, to)?;
+
+    Ok(runs
+        .iter()
+        .filter(|run| run.server == s_name)
+        .map(|run| run_hours(run, from, to, now))
+        .sum())
+}
+
+/// Remaining hours and their costs, if a cost rate is configured.
+pub fn describe_remaining(budget: &BudgetConfig, used: f64) -> String {
+    let remaining = (budget.hours - used).max(0.0);
+
+    match budget.cost_per_hour {
+        Some(rate) => format!(
+            "{:.1} of {:.0} h ({:.2} of {:.2})",
+            remaining,
+            budget.hours,
+            remaining * rate,
+            budget.hours * rate
+        ),
+        None => format!("{:.1} of {:.0} h", remaining, budget.hours),
+    }
+}
+
+pub fn spawn_budget_worker(ctx: Arc<Context>) {
+    info!("Spawning budget worker.");
+
+    tokio::spawn(async move { budget_worker(ctx).await });
+}
+
+async fn budget_worker(ctx: Arc<Context>) {
+    // Servers warned about in the current month
+    let mut warned = HashSet::new();
+    let mut month = current_month();
+
+    loop {
+        sleep(BUDGET_CHECK_INTERVAL).await;
+
+        if current_month() != month {
+            month = current_month();
+            warned.clear();
+        }
+
+        let servers = {
+            let data = ctx.data.read().await;
+            let config = data.get::<ConfigKey>().unwrap();
+            config
+                .servers
+                .snapshot()
+                .iter()
+                .filter(|(_, c)| c.budget.is_some())
+                .map(|(s_name, c)| (s_name.clone(), c.clone()))
+                .collect::<Vec<_>>()
+        };
+
+        for (s_name, server_conf) in servers {
+            if let Err(why) = check_budget(&ctx, &s_name, &server_conf, month, &mut warned).await {
+                warn!("Error checking budget of {}: {}", s_name, why);
+            }
+        }
+    }
+}
+
+async fn check_budget(
+    ctx: &Context,
+    s_name: &str,
+    server_conf: &ServerConfig,
+    month: NaiveDate,
+    warned: &mut HashSet<String>,
+) -> SimpleResult<()> {
+    let data = ctx.data.read().await;
+    let client = data.get::<AzureClientKey>().unwrap();
+    let locks = data.get::<InstanceLockKey>().unwrap();
+    let sql = data.get::<SqlKey>().unwrap();
+
+    let budget = server_conf.budget.as_ref().unwrap();
+
+    sync_runs(client, sql, locks, s_name, server_conf).await?;
+
+    if !is_open(&mut sql.connection.get()?, s_name)? {
+        return Ok(());
+    }
+
+    let used = used_hours(sql, s_name, month)?;
+
+    let announce = |header: String| match server_conf.announce_channel {
+        Some(channel) => ProgressMessage::in_channel(ChannelId(channel), header),
+        None => ProgressMessage::silent(),
+    };
+
+    if used < budget.hours {
+        if used >= budget.hours * WARNING_THRESHOLD && warned.insert(s_name.to_owned()) {
+            info!("{} is about to exceed its monthly budget.", s_name);
+            let mut progress = announce(format!(
+                "**{}** is about to exceed its monthly budget:",
+                s_name
+            ));
+            progress!(
+                progress,
+                ctx,
+                format!(
+                    "Remaining: {}. The server will be stopped once it is used up.",
+                    describe_remaining(budget, used)
+                )
+            );
+        }
+
+        return Ok(());
+    }
+
+    let _l = match locks.get(s_name).await.try_lock_owned() {
+        Ok(l) => l,
+        Err(_) => {
+            info!(
+                "Budget stop of {} blocked by another task, retrying later.",
+                s_name
+            );
+            return Ok(());
+        }
+    };
+
+    info!("{} exceeded its monthly budget, stopping it.", s_name);
+
+    let mut progress = announce(format!(
+        "**{}** used up its monthly budget of {:.0} h, stopping it.",
+        s_name, budget.hours
+    ));
+
+    // The budget is enforced even if a running server depends on this one
+    let res = stop_with_dependencies(ctx, &data, s_name, server_conf, &mut progress, None).await;
+
+    if let Err(ref why) = res {
+        progress!(progress, ctx, format!("Failed: {}", why));
+    }
+
+    res
+}
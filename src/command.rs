@@ -1,37 +1,516 @@
-use crate::SimpleResult;
+use crate::azure::management::vm_run_cmd::ScriptOutput;
+use crate::{SimpleError, SimpleResult};
+use async_trait::async_trait;
+use chrono::{DateTime, Utc};
+use serenity::builder::CreateComponents;
 use serenity::client::Context;
-use serenity::model::channel::Message;
+use serenity::model::channel::{AttachmentType, Message};
+use serenity::model::id::{ChannelId, GuildId};
+use serenity::model::interactions::application_command::ApplicationCommandInteraction;
+use serenity::model::interactions::autocomplete::AutocompleteInteraction;
+use serenity::model::interactions::message_component::{ButtonStyle, MessageComponentInteraction};
+use serenity::model::user::User;
 use serenity::prelude::TypeMapKey;
 use std::borrow::Cow;
 use std::collections::hash_map::Entry;
 use std::collections::HashMap;
+use std::fs;
+use std::ops::Deref;
+use std::path::Path;
+use std::sync::atomic::{AtomicBool, Ordering};
 use std::sync::Arc;
-use tokio::sync::{Mutex, RwLock};
+use tokio::sync::{Mutex, Notify, RwLock};
 
+pub mod audit;
+pub mod backup;
+pub mod exec;
+pub mod list;
 pub mod ping;
+pub mod resize;
+pub mod restart;
+pub mod schedule;
 pub mod start;
+pub mod status;
 pub mod stop;
+pub mod usage;
 
 pub const CMD_PREFIX: &str = "~";
 
+/// Stands for an omitted optional argument, which is followed by further arguments.
+pub const OMITTED_ARG: &str = "-";
+
+// Leaves enough room for the surrounding text within discord's 2000 character limit
+const MAX_INLINE_OUTPUT: usize = 1800;
+
+enum InvocationSource<'a> {
+    Message(&'a Message),
+    /// Slash command or button press, whose response has been deferred
+    Interaction(&'a dyn DeferredResponse),
+    /// Autocompletes are answered with choices, never with messages
+    Autocomplete,
+}
+
+/// Replies to an interaction, after its response has been deferred.
+#[async_trait]
+trait DeferredResponse: Sync {
+    async fn edit_response(
+        &self,
+        ctx: &Context,
+        content: String,
+        buttons: &[(ProgressButton, String)],
+    ) -> serenity::Result<Message>;
+
+    async fn follow_up(
+        &self,
+        ctx: &Context,
+        content: Option<String>,
+        buttons: &[(ProgressButton, String)],
+        file: Option<AttachmentType<'_>>,
+    ) -> serenity::Result<Message>;
+}
+
+macro_rules! deferred_response {
+    ($interaction:ty) => {
+        #[async_trait]
+        impl DeferredResponse for $interaction {
+            async fn edit_response(
+                &self,
+                ctx: &Context,
+                content: String,
+                buttons: &[(ProgressButton, String)],
+            ) -> serenity::Result<Message> {
+                self.edit_original_interaction_response(ctx, |r| {
+                    r.content(content).components(|c| add_buttons(c, buttons))
+                })
+                .await
+            }
+
+            async fn follow_up(
+                &self,
+                ctx: &Context,
+                content: Option<String>,
+                buttons: &[(ProgressButton, String)],
+                file: Option<AttachmentType<'_>>,
+            ) -> serenity::Result<Message> {
+                self.create_followup_message(ctx, |r| {
+                    if let Some(content) = content {
+                        r.content(content);
+                    }
+                    if let Some(file) = file {
+                        r.add_file(file);
+                    }
+                    r.components(|c| add_buttons(c, buttons))
+                })
+                .await
+            }
+        }
+    };
+}
+
+deferred_response!(ApplicationCommandInteraction);
+deferred_response!(MessageComponentInteraction);
+
+/// A single command invocation, either by a prefixed message, a slash command or a button.
+pub struct Invocation<'a> {
+    pub name: &'a str,
+    pub args: Vec<String>,
+    pub user: &'a User,
+    pub guild_id: Option<GuildId>,
+    pub channel_id: ChannelId,
+    /// When discord received the message or interaction
+    pub received_at: DateTime<Utc>,
+    source: InvocationSource<'a>,
+    responded: AtomicBool,
+}
+
+impl<'a> Invocation<'a> {
+    pub fn from_message(msg: &'a Message, name: &'a str) -> Invocation<'a> {
+        let args = msg
+            .content
+            .get(CMD_PREFIX.len() + name.len()..)
+            .unwrap_or_default()
+            .split_whitespace()
+            .map(ToOwned::to_owned)
+            .collect();
+
+        Invocation {
+            name,
+            args,
+            user: &msg.author,
+            guild_id: msg.guild_id,
+            channel_id: msg.channel_id,
+            received_at: *msg.timestamp,
+            source: InvocationSource::Message(msg),
+            responded: AtomicBool::new(false),
+        }
+    }
+
+    /// The interaction response has to be deferred before the invocation is executed.
+    pub fn from_interaction(
+        interaction: &'a ApplicationCommandInteraction,
+        args: Vec<String>,
+    ) -> Invocation<'a> {
+        Invocation {
+            name: &interaction.data.name,
+            args,
+            user: &interaction.user,
+            guild_id: interaction.guild_id,
+            channel_id: interaction.channel_id,
+            received_at: *interaction.id.created_at(),
+            source: InvocationSource::Interaction(interaction),
+            responded: AtomicBool::new(false),
+        }
+    }
+
+    /// Invocation the user is currently typing, only used for permission checks.
+    pub fn from_autocomplete(
+        interaction: &'a AutocompleteInteraction,
+        args: Vec<String>,
+    ) -> Invocation<'a> {
+        Invocation {
+            name: &interaction.data.name,
+            args,
+            user: &interaction.user,
+            guild_id: interaction.guild_id,
+            channel_id: interaction.channel_id,
+            received_at: *interaction.id.created_at(),
+            source: InvocationSource::Autocomplete,
+            responded: AtomicBool::new(false),
+        }
+    }
+
+    /// Invocation by a button press, the response has to be deferred with a new message.
+    pub fn from_component(
+        interaction: &'a MessageComponentInteraction,
+        name: &'a str,
+        args: Vec<String>,
+    ) -> Invocation<'a> {
+        Invocation {
+            name,
+            args,
+            user: &interaction.user,
+            guild_id: interaction.guild_id,
+            channel_id: interaction.channel_id,
+            received_at: *interaction.id.created_at(),
+            source: InvocationSource::Interaction(interaction),
+            responded: AtomicBool::new(false),
+        }
+    }
+
+    /// Replies to the invocation, the first reply to an interaction fills the deferred response.
+    pub async fn respond(&self, ctx: &Context, content: impl ToString) -> SimpleResult<Message> {
+        self.send(ctx, content.to_string(), &[]).await
+    }
+
+    /// Replies with the content and a file, interactions get the file as follow-up message.
+    pub async fn respond_with_file(
+        &self,
+        ctx: &Context,
+        content: impl ToString,
+        filename: &str,
+        data: Vec<u8>,
+    ) -> SimpleResult<Message> {
+        let file = AttachmentType::Bytes {
+            data: Cow::Owned(data),
+            filename: filename.to_owned(),
+        };
+
+        self.reply(ctx, content.to_string(), &[], Some(file)).await
+    }
+
+    /// Replies with the script output, inline if it's short enough and as attachment otherwise.
+    pub async fn respond_with_output(
+        &self,
+        ctx: &Context,
+        header: impl ToString,
+        output: &ScriptOutput,
+    ) -> SimpleResult<Message> {
+        match inline_output(output) {
+            Some(inline) => {
+                self.respond(ctx, format!("{}{}", header.to_string(), inline))
+                    .await
+            }
+            None => {
+                self.respond_with_file(ctx, header, "output.txt", output.to_string().into_bytes())
+                    .await
+            }
+        }
+    }
+
+    async fn send(
+        &self,
+        ctx: &Context,
+        content: String,
+        buttons: &[(ProgressButton, String)],
+    ) -> SimpleResult<Message> {
+        self.reply(ctx, content, buttons, None).await
+    }
+
+    /// Sends a new reply, the first one to an interaction fills its deferred response.
+    async fn reply(
+        &self,
+        ctx: &Context,
+        content: String,
+        buttons: &[(ProgressButton, String)],
+        file: Option<AttachmentType<'_>>,
+    ) -> SimpleResult<Message> {
+        let res = match self.source {
+            InvocationSource::Message(msg) => {
+                msg.channel_id
+                    .send_message(ctx, |m| {
+                        if let Some(file) = file {
+                            m.add_file(file);
+                        }
+                        m.content(content)
+                            .reference_message(msg)
+                            .components(|c| add_buttons(c, buttons))
+                    })
+                    .await
+            }
+            InvocationSource::Interaction(interaction) => {
+                if self.responded.swap(true, Ordering::Relaxed) {
+                    interaction
+                        .follow_up(ctx, Some(content), buttons, file)
+                        .await
+                } else if file.is_some() {
+                    // Editing the deferred response doesn't support attachments
+                    interaction.edit_response(ctx, content, buttons).await?;
+                    interaction.follow_up(ctx, None, &[], file).await
+                } else {
+                    interaction.edit_response(ctx, content, buttons).await
+                }
+            }
+            InvocationSource::Autocomplete => return Err(SimpleError::NotReplyable),
+        };
+
+        res.map_err(Into::into)
+    }
+
+    /// Edits the deferred interaction response, returns `None` for prefixed messages.
+    async fn edit_original(
+        &self,
+        ctx: &Context,
+        content: String,
+        buttons: &[(ProgressButton, String)],
+    ) -> Option<SimpleResult<Message>> {
+        let interaction = match self.source {
+            InvocationSource::Interaction(interaction) => interaction,
+            _ => return None,
+        };
+
+        let res = interaction.edit_response(ctx, content, buttons).await;
+        self.responded.store(true, Ordering::Relaxed);
+        Some(res.map_err(Into::into))
+    }
+}
+
+/// Output formatted as code block, `None` if it's too long to fit into a message.
+pub fn inline_output(output: &ScriptOutput) -> Option<String> {
+    let output = output.to_string();
+
+    if output.is_empty() {
+        Some(String::new())
+    } else if output.len() <= MAX_INLINE_OUTPUT {
+        Some(format!("\n```\n{}\n```", output))
+    } else {
+        None
+    }
+}
+
+/// Reads the script of a server, a missing or non utf-8 script is an error instead of a panic.
+pub fn read_script(path: Option<&Path>, kind: &str) -> SimpleResult<String> {
+    let path =
+        path.ok_or_else(|| SimpleError::UsageError(format!("No {} script configured.", kind)))?;
+
+    fs::read_to_string(path).map_err(Into::into)
+}
+
+/// Buttons attached to progress messages, pressing one invokes the command of the same name.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ProgressButton {
+    Cancel,
+    Stop,
+    Status,
+}
+
+impl ProgressButton {
+    pub fn name(self) -> &'static str {
+        match self {
+            ProgressButton::Cancel => "cancel",
+            ProgressButton::Stop => "stop",
+            ProgressButton::Status => "status",
+        }
+    }
+
+    fn label(self) -> &'static str {
+        match self {
+            ProgressButton::Cancel => "Cancel",
+            ProgressButton::Stop => "Stop",
+            ProgressButton::Status => "Status",
+        }
+    }
+
+    fn style(self) -> ButtonStyle {
+        match self {
+            ProgressButton::Cancel | ProgressButton::Stop => ButtonStyle::Danger,
+            ProgressButton::Status => ButtonStyle::Secondary,
+        }
+    }
+
+    /// Custom id of the button, in the format `<name>:<instance>`.
+    pub fn custom_id(self, s_name: &str) -> String {
+        format!("{}:{}", self.name(), s_name)
+    }
+
+    pub fn parse(custom_id: &str) -> Option<(ProgressButton, &str)> {
+        let (name, s_name) = custom_id.split_once(':')?;
+        let button = [
+            ProgressButton::Cancel,
+            ProgressButton::Stop,
+            ProgressButton::Status,
+        ]
+        .into_iter()
+        .find(|b| b.name() == name)?;
+
+        Some((button, s_name))
+    }
+}
+
+fn add_buttons<'b>(
+    c: &'b mut CreateComponents,
+    buttons: &[(ProgressButton, String)],
+) -> &'b mut CreateComponents {
+    if !buttons.is_empty() {
+        c.create_action_row(|r| {
+            for (button, s_name) in buttons {
+                r.create_button(|b| {
+                    b.custom_id(button.custom_id(s_name))
+                        .label(button.label())
+                        .style(button.style())
+                });
+            }
+            r
+        });
+    }
+
+    c
+}
+
+enum ProgressTarget<'a> {
+    Invocation(&'a Invocation<'a>),
+    Channel(ChannelId),
+    Silent,
+}
+
 pub struct ProgressMessage<'a> {
-    user_msg: &'a Message,
+    target: ProgressTarget<'a>,
+    header: Option<String>,
+    content: String,
+    buttons: Vec<(ProgressButton, String)>,
     bot_msg: Option<Message>,
 }
 
 impl<'a> ProgressMessage<'a> {
-    pub fn new(user_msg: &'a Message) -> ProgressMessage<'a> {
+    pub fn new(inv: &'a Invocation<'a>) -> ProgressMessage<'a> {
+        ProgressMessage {
+            target: ProgressTarget::Invocation(inv),
+            header: None,
+            content: String::new(),
+            buttons: Vec::new(),
+            bot_msg: None,
+        }
+    }
+
+    /// Progress message posted into a channel, used by tasks not triggered by a user message.
+    pub fn in_channel(channel: ChannelId, header: impl ToString) -> ProgressMessage<'static> {
+        ProgressMessage {
+            target: ProgressTarget::Channel(channel),
+            header: Some(header.to_string()),
+            content: String::new(),
+            buttons: Vec::new(),
+            bot_msg: None,
+        }
+    }
+
+    /// Progress message which is only logged, but never sent.
+    pub fn silent() -> ProgressMessage<'static> {
         ProgressMessage {
-            user_msg,
+            target: ProgressTarget::Silent,
+            header: None,
+            content: String::new(),
+            buttons: Vec::new(),
             bot_msg: None,
         }
     }
 
+    /// Line shown above every update.
+    pub fn set_header(&mut self, header: impl ToString) {
+        self.header = Some(header.to_string());
+    }
+
+    /// Channel the message is posted in, `None` if it's silent.
+    pub fn channel(&self) -> Option<ChannelId> {
+        match self.target {
+            ProgressTarget::Invocation(inv) => Some(inv.channel_id),
+            ProgressTarget::Channel(channel) => Some(channel),
+            ProgressTarget::Silent => None,
+        }
+    }
+
+    /// Sets the buttons sent with the next update, an empty slice removes them.
+    pub fn set_buttons(&mut self, s_name: &str, buttons: &[ProgressButton]) {
+        self.buttons = buttons.iter().map(|b| (*b, s_name.to_owned())).collect();
+    }
+
     pub async fn update(&mut self, ctx: &Context, msg: impl ToString) -> SimpleResult<()> {
+        self.content = msg.to_string();
+        self.send(ctx).await
+    }
+
+    /// Resends the last update, e.g. after the buttons changed.
+    pub async fn refresh(&mut self, ctx: &Context) -> SimpleResult<()> {
+        if self.bot_msg.is_none() {
+            return Ok(());
+        }
+
+        self.send(ctx).await
+    }
+
+    async fn send(&mut self, ctx: &Context) -> SimpleResult<()> {
+        let msg = match self.header {
+            Some(ref header) => format!("{}\n{}", header, self.content),
+            None => self.content.clone(),
+        };
+        let buttons = &self.buttons;
+
+        // Interaction responses are edited through the interaction webhook
+        if let ProgressTarget::Invocation(inv) = self.target {
+            if let Some(res) = inv.edit_original(ctx, msg.clone(), buttons).await {
+                self.bot_msg = Some(res?);
+                return Ok(());
+            }
+        }
+
         let res = match self.bot_msg {
-            Some(ref mut existing) => existing.edit(ctx, |m| m.content(msg)).await,
+            Some(ref mut existing) => {
+                existing
+                    .edit(ctx, |m| {
+                        m.content(msg).components(|c| add_buttons(c, buttons))
+                    })
+                    .await
+            }
             None => {
-                let msg = self.user_msg.reply(ctx, msg.to_string()).await?;
+                let msg = match self.target {
+                    ProgressTarget::Invocation(inv) => inv.send(ctx, msg, buttons).await?,
+                    ProgressTarget::Channel(channel) => {
+                        channel
+                            .send_message(ctx, |m| {
+                                m.content(msg).components(|c| add_buttons(c, buttons))
+                            })
+                            .await?
+                    }
+                    ProgressTarget::Silent => return Ok(()),
+                };
                 self.bot_msg = Some(msg);
                 Ok(())
             }
@@ -78,13 +557,104 @@ impl InstanceLocks {
     }
 }
 
+pub struct CancelKey;
+
+impl TypeMapKey for CancelKey {
+    type Value = CancelTokens;
+}
+
+/// Cancellation tokens of the currently running tasks, keyed by instance.
+#[derive(Default)]
+pub struct CancelTokens(std::sync::Mutex<HashMap<String, CancelToken>>);
+
+impl CancelTokens {
+    /// The token is unregistered, when the returned registration is dropped.
+    pub fn register(&self, key: impl ToString) -> CancelRegistration<'_> {
+        self.register_token(key, CancelToken::default())
+    }
+
+    /// Registers an existing token, e.g. for a task running on behalf of another one.
+    pub fn register_token(&self, key: impl ToString, token: CancelToken) -> CancelRegistration<'_> {
+        let key = key.to_string();
+        self.0.lock().unwrap().insert(key.clone(), token.clone());
+
+        CancelRegistration {
+            tokens: self,
+            key,
+            token,
+        }
+    }
+
+    /// Returns false, if there is no running task for the key.
+    pub fn cancel(&self, key: &str) -> bool {
+        match self.0.lock().unwrap().get(key) {
+            Some(token) => {
+                token.cancel();
+                true
+            }
+            None => false,
+        }
+    }
+}
+
+pub struct CancelRegistration<'a> {
+    tokens: &'a CancelTokens,
+    key: String,
+    token: CancelToken,
+}
+
+impl Deref for CancelRegistration<'_> {
+    type Target = CancelToken;
+
+    fn deref(&self) -> &CancelToken {
+        &self.token
+    }
+}
+
+impl Drop for CancelRegistration<'_> {
+    fn drop(&mut self) {
+        self.tokens.0.lock().unwrap().remove(&self.key);
+    }
+}
+
+#[derive(Clone, Default)]
+pub struct CancelToken(Arc<(AtomicBool, Notify)>);
+
+impl CancelToken {
+    pub fn cancel(&self) {
+        self.0 .0.store(true, Ordering::Relaxed);
+        self.0 .1.notify_waiters();
+    }
+
+    pub fn is_cancelled(&self) -> bool {
+        self.0 .0.load(Ordering::Relaxed)
+    }
+
+    /// Completes once the token is cancelled.
+    pub async fn cancelled(&self) {
+        let notified = self.0 .1.notified();
+        if self.is_cancelled() {
+            return;
+        }
+
+        notified.await;
+    }
+}
+
 macro_rules! usage_error_ {
     ($($args:expr),* $(,)?) => {
         $crate::SimpleError::UsageError(format!($($args),*))
     }
 }
 
-use usage_error_ as usage_error;
+pub(crate) use usage_error_ as usage_error;
+
+pub fn server_name<'a>(inv: &'a Invocation<'_>) -> SimpleResult<&'a str> {
+    inv.args
+        .get(0)
+        .map(String::as_str)
+        .ok_or_else(|| usage_error!("Syntax: {}{} <instance>.", CMD_PREFIX, inv.name))
+}
 
 pub type InstanceLock = Arc<Mutex<()>>;
 
@@ -111,14 +681,14 @@ macro_rules! _progress {
     };
 }
 
-use _progress as progress;
+pub(crate) use _progress as progress;
 
 macro_rules! _stop_on_timeout {
-    ($res:expr, $client:expr, $sub:expr, $rg:expr, $vm:expr) => {
+    ($res:expr, $provider:expr) => {
         match $res {
-            Err($crate::SimpleError::Timeout) => {
-                $client.deallocate($sub, $rg, $vm).await?.wait().await?;
-                Err($crate::SimpleError::Timeout)
+            Err(e @ ($crate::SimpleError::Timeout | $crate::SimpleError::Cancelled)) => {
+                $provider.power_off().await?;
+                Err(e)
             }
             r => r,
         }
@@ -126,3 +696,15 @@ macro_rules! _stop_on_timeout {
 }
 
 use _stop_on_timeout as stop_on_timeout;
+
+/// Awaits the future, unless the token is cancelled first.
+macro_rules! _cancellable {
+    ($token:expr, $fut:expr) => {
+        tokio::select! {
+            res = $fut => res,
+            _ = $token.cancelled() => Err($crate::SimpleError::Cancelled),
+        }
+    };
+}
+
+use _cancellable as cancellable;
@@ -0,0 +1,110 @@
+use crate::command::{usage_error, Invocation, MAX_INLINE_OUTPUT, OMITTED_ARG};
+use crate::sql::audit::{latest, AuditEntry, AuditFilter};
+use crate::sql::SqlKey;
+use crate::{SimpleResult, CMD_PREFIX};
+use serenity::client::Context;
+use serenity::framework::standard::macros::command;
+use serenity::framework::standard::CommandResult;
+use serenity::model::channel::Message;
+use serenity::utils::parse_username;
+
+const DEFAULT_LIMIT: i64 = 10;
+const MAX_LIMIT: i64 = 100;
+
+#[command]
+async fn audit(ctx: &Context, msg: &Message) -> CommandResult {
+    execute(ctx, &Invocation::from_message(msg, "audit")).await?;
+
+    Ok(())
+}
+
+pub async fn execute(ctx: &Context, inv: &Invocation<'_>) -> SimpleResult<()> {
+    let entries = {
+        let data = ctx.data.read().await;
+        let sql = data.get::<SqlKey>().unwrap();
+
+        let (filter, limit) = parse_args(inv)?;
+
+        latest(&mut sql.connection.get()?, filter, limit)?
+    };
+
+    if entries.is_empty() {
+        inv.respond(ctx, "No matching commands recorded.").await?;
+        return Ok(());
+    }
+
+    let content = entries.iter().map(describe).collect::<Vec<_>>().join("\n");
+
+    if content.len() > MAX_INLINE_OUTPUT {
+        inv.respond_with_file(
+            ctx,
+            format!("Last {} commands:", entries.len()),
+            "audit.txt",
+            content.into_bytes(),
+        )
+        .await?;
+    } else {
+        inv.respond(ctx, content).await?;
+    }
+
+    Ok(())
+}
+
+/// The optional filter is either a user, given as mention or id, or an instance.
+///
+/// Instances aren't checked against the configured ones, so the history of removed servers stays readable.
+fn parse_args<'a>(inv: &'a Invocation<'_>) -> SimpleResult<(AuditFilter<'a>, i64)> {
+    let syntax = || {
+        usage_error!(
+            "Syntax: {}audit [user|instance|-] [limit], with a limit of at most {}.",
+            CMD_PREFIX,
+            MAX_LIMIT
+        )
+    };
+
+    let parse_limit = |arg: &str| match arg.parse::<i64>() {
+        Ok(limit) if (1..=MAX_LIMIT).contains(&limit) => Ok(limit),
+        _ => Err(syntax()),
+    };
+
+    let parse_filter = |arg: &'a str| match parse_username(arg).or_else(|| arg.parse().ok()) {
+        Some(id) if id > MAX_LIMIT as u64 => Some(AuditFilter::User(id as i64)),
+        // Numbers up to the maximum are limits
+        Some(_) => None,
+        None => Some(AuditFilter::Instance(arg)),
+    };
+
+    match inv.args.as_slice() {
+        [] => Ok((AuditFilter::All, DEFAULT_LIMIT)),
+        [filter] if filter == OMITTED_ARG => Ok((AuditFilter::All, DEFAULT_LIMIT)),
+        [arg] => match parse_filter(arg.as_str()) {
+            Some(filter) => Ok((filter, DEFAULT_LIMIT)),
+            None => Ok((AuditFilter::All, parse_limit(arg)?)),
+        },
+        [filter, limit] if filter == OMITTED_ARG => Ok((AuditFilter::All, parse_limit(limit)?)),
+        [filter, limit] => Ok((
+            parse_filter(filter.as_str()).ok_or_else(syntax)?,
+            parse_limit(limit)?,
+        )),
+        _ => Err(syntax()),
+    }
+}
+
+fn describe(entry: &AuditEntry) -> String {
+    let command = format!("{}{} {}", CMD_PREFIX, entry.command, entry.arguments);
+
+    let outcome = match (entry.permitted, entry.error.as_ref()) {
+        (_, Some(why)) => format!("failed: {}", why),
+        (false, None) => "denied".to_owned(),
+        (true, None) => "succeeded".to_owned(),
+    };
+
+    format!(
+        "{} {} `{}` {} ({:.1} s)",
+        entry.created_at.format("%Y-%m-%d %H:%M UTC"),
+        entry.user_tag,
+        command.trim_end(),
+        outcome,
+        entry.duration_ms as f64 / 1000.0
+    )
+}
@@ -0,0 +1,417 @@
+use crate::azure::management::snapshot::{Snapshot, SnapshotClient};
+use crate::azure::management::vm::{PowerState, VmClient};
+use crate::azure::AzureClient;
+use crate::command::{
+    instance_lock, progress, server_name, usage_error, Invocation, ProgressMessage,
+};
+use crate::conf::VmConfig;
+use crate::permission::has_permission;
+use crate::permission::rbac::RbacPermission;
+use crate::{AzureClientKey, ConfigKey, SimpleResult, CMD_PREFIX};
+use chrono::Utc;
+use log::{info, warn};
+use serenity::client::Context;
+use serenity::framework::standard::macros::command;
+use serenity::framework::standard::CommandResult;
+use serenity::model::channel::Message;
+use std::collections::{BTreeMap, HashMap};
+use std::time::Duration;
+
+const TIMEOUT: Duration = Duration::from_secs(600);
+
+// Tags identifying the snapshots of a backup
+const TAG_SERVER: &str = "r6v3-server";
+const TAG_BACKUP: &str = "r6v3-backup";
+const TAG_DISK: &str = "r6v3-disk";
+
+#[command]
+async fn backup(ctx: &Context, msg: &Message) -> CommandResult {
+    execute_backup(ctx, &Invocation::from_message(msg, "backup")).await?;
+
+    Ok(())
+}
+
+#[command]
+async fn backups(ctx: &Context, msg: &Message) -> CommandResult {
+    execute_backups(ctx, &Invocation::from_message(msg, "backups")).await?;
+
+    Ok(())
+}
+
+#[command]
+async fn restore(ctx: &Context, msg: &Message) -> CommandResult {
+    execute_restore(ctx, &Invocation::from_message(msg, "restore")).await?;
+
+    Ok(())
+}
+
+/// Snapshots all managed disks of the server and deletes the backups exceeding the retention.
+pub async fn execute_backup(ctx: &Context, inv: &Invocation<'_>) -> SimpleResult<()> {
+    let data = ctx.data.read().await;
+
+    let s_name = server_name(inv)?;
+
+    let config = data.get::<ConfigKey>().unwrap();
+    let client = data.get::<AzureClientKey>().unwrap();
+
+    let servers = config.servers.snapshot();
+    let server_conf = servers
+        .get(s_name)
+        .ok_or_else(|| usage_error!("Invalid instance."))?;
+
+    let backup_conf = server_conf
+        .backup
+        .as_ref()
+        .ok_or_else(|| usage_error!("Backups are not configured for {}.", s_name))?;
+
+    let _l = instance_lock!(data, s_name)?;
+
+    let vm = server_conf.vm()?;
+    let model = client.model(&vm.sub, &vm.rg, &vm.name).await?;
+    let disks = model
+        .properties
+        .storage_profile
+        .as_ref()
+        .map(|p| p.managed_disks())
+        .unwrap_or_default();
+
+    if disks.is_empty() {
+        return Err(usage_error!("{} has no managed disks.", s_name));
+    }
+
+    let label = Utc::now().format("%Y%m%d-%H%M%S").to_string();
+    let mut progress = ProgressMessage::new(inv);
+    let mut created = Vec::new();
+
+    let res: SimpleResult<()> = async {
+        for (slot, disk) in disks.iter() {
+            progress!(progress, ctx, format!("Snapshotting disk {} ...", slot));
+            info!(
+                "Snapshotting disk {} of {} for backup {}.",
+                slot, s_name, label
+            );
+
+            let tags = HashMap::from([
+                (TAG_SERVER.to_owned(), s_name.to_owned()),
+                (TAG_BACKUP.to_owned(), label.clone()),
+                (TAG_DISK.to_owned(), slot.clone()),
+            ]);
+            let name = format!("{}-{}-{}", vm.name, slot, label);
+
+            let task = client
+                .create_snapshot(
+                    &vm.sub,
+                    &vm.rg,
+                    &name,
+                    &model.location,
+                    &disk.managed_disk.as_ref().unwrap().id,
+                    &tags,
+                )
+                .await?;
+            created.push(name);
+            task.timeout(Some(TIMEOUT)).wait().await?;
+        }
+
+        Ok(())
+    }
+    .await;
+
+    // A partial backup would be listed and restored like a complete one
+    if let Err(why) = res {
+        progress!(
+            progress,
+            ctx,
+            "Failed, deleting the snapshots taken so far ..."
+        );
+        warn!(
+            "Error creating backup {} of {}, deleting its snapshots: {}",
+            label, s_name, why
+        );
+
+        for name in created {
+            if let Err(inner) = client.delete_snapshot(&vm.sub, &vm.rg, &name).await {
+                warn!("Error deleting snapshot {}: {}", name, inner);
+            }
+        }
+
+        return Err(why);
+    }
+
+    let mut backups = list_backups(client, vm, s_name).await?;
+    let mut deleted = 0;
+
+    // Labels sort by creation time, the oldest backups are deleted first
+    while backups.len() > backup_conf.retention {
+        let old = backups.keys().next().cloned().unwrap();
+        let snapshots = backups.remove(&old).unwrap();
+
+        progress!(progress, ctx, format!("Deleting old backup {} ...", old));
+        info!("Deleting backup {} of {}.", old, s_name);
+
+        for snapshot in snapshots {
+            client
+                .delete_snapshot(&vm.sub, &vm.rg, &snapshot.name)
+                .await?;
+        }
+
+        deleted += 1;
+    }
+
+    progress!(
+        progress,
+        ctx,
+        format!(
+            "Created backup `{}`, deleted {} old backup(s).",
+            label, deleted
+        )
+    );
+    info!("Successfully created backup {} of {}.", label, s_name);
+
+    Ok(())
+}
+
+pub async fn execute_backups(ctx: &Context, inv: &Invocation<'_>) -> SimpleResult<()> {
+    let data = ctx.data.read().await;
+
+    let s_name = server_name(inv)?;
+
+    let config = data.get::<ConfigKey>().unwrap();
+    let client = data.get::<AzureClientKey>().unwrap();
+
+    let servers = config.servers.snapshot();
+    let server_conf = servers
+        .get(s_name)
+        .ok_or_else(|| usage_error!("Invalid instance."))?;
+
+    let backups = list_backups(client, server_conf.vm()?, s_name).await?;
+
+    if backups.is_empty() {
+        inv.respond(ctx, format!("There are no backups of {}.", s_name))
+            .await?;
+        return Ok(());
+    }
+
+    let mut lines = vec![format!("Backups of **{}**, newest first:", s_name)];
+    for (label, snapshots) in backups.iter().rev() {
+        let created = snapshots
+            .iter()
+            .filter_map(|s| s.properties.time_created)
+            .min()
+            .map(|t| format!("<t:{}:f>", t.timestamp()))
+            .unwrap_or_else(|| "unknown".to_owned());
+
+        let disks = snapshots
+            .iter()
+            .map(|s| match s.properties.disk_size_gb {
+                Some(size) => format!("{} ({} GB)", disk_slot(s), size),
+                None => disk_slot(s).to_owned(),
+            })
+            .collect::<Vec<_>>();
+
+        lines.push(format!(
+            "`{}` created {}: {}",
+            label,
+            created,
+            disks.join(", ")
+        ));
+    }
+
+    inv.respond(ctx, lines.join("\n")).await?;
+
+    Ok(())
+}
+
+/// Replaces the disks of the deallocated server by new ones created from the backup.
+///
+/// The previous disks are kept, so a restore can be undone in the portal.
+pub async fn execute_restore(ctx: &Context, inv: &Invocation<'_>) -> SimpleResult<()> {
+    let data = ctx.data.read().await;
+
+    let (s_name, label) = match inv.args.as_slice() {
+        [s_name, label] => (s_name.as_str(), label.as_str()),
+        _ => {
+            return Err(usage_error!(
+                "Syntax: {}restore <instance> <backup>.",
+                CMD_PREFIX
+            ))
+        }
+    };
+
+    let config = data.get::<ConfigKey>().unwrap();
+    let client = data.get::<AzureClientKey>().unwrap();
+
+    let servers = config.servers.snapshot();
+    let server_conf = servers
+        .get(s_name)
+        .ok_or_else(|| usage_error!("Invalid instance."))?;
+
+    let _l = instance_lock!(data, s_name)?;
+
+    let vm = server_conf.vm()?;
+    let power_state = client
+        .instance_view(&vm.sub, &vm.rg, &vm.name)
+        .await?
+        .power_state();
+
+    if power_state != PowerState::Deallocated {
+        return Err(usage_error!(
+            "The server has to be stopped for restoring, it is {}.",
+            power_state
+        ));
+    }
+
+    let snapshots = list_backups(client, vm, s_name)
+        .await?
+        .remove(label)
+        .ok_or_else(|| {
+            usage_error!(
+                "Unknown backup, see {}backups {} for the available ones.",
+                CMD_PREFIX,
+                s_name
+            )
+        })?;
+
+    let model = client.model(&vm.sub, &vm.rg, &vm.name).await?;
+    let mut profile = model
+        .properties
+        .storage_profile
+        .ok_or_else(|| usage_error!("{} has no managed disks.", s_name))?;
+
+    // Check every disk before touching anything
+    for snapshot in snapshots.iter() {
+        if profile.disk_mut(disk_slot(snapshot)).is_none() {
+            return Err(usage_error!(
+                "Disk {} of the backup is not attached to {} anymore.",
+                disk_slot(snapshot),
+                s_name
+            ));
+        }
+    }
+
+    // Disks attached after the backup would keep their contents
+    let uncovered = profile
+        .managed_disks()
+        .into_iter()
+        .map(|(slot, _)| slot)
+        .filter(|slot| !snapshots.iter().any(|s| disk_slot(s) == slot.as_str()))
+        .collect::<Vec<_>>();
+    if !uncovered.is_empty() {
+        return Err(usage_error!(
+            "The backup doesn't cover disk(s) {} of {}.",
+            uncovered.join(", "),
+            s_name
+        ));
+    }
+
+    let mut progress = ProgressMessage::new(inv);
+    let restored_at = Utc::now().format("%Y%m%d-%H%M%S").to_string();
+    let mut previous = Vec::new();
+
+    for snapshot in snapshots.iter() {
+        let slot = disk_slot(snapshot);
+        let attached = profile.disk_mut(slot).unwrap();
+        let current = client
+            .disk(&attached.managed_disk.as_ref().unwrap().id)
+            .await?;
+        let name = format!("{}-{}-restored-{}", vm.name, slot, restored_at);
+
+        progress!(progress, ctx, format!("Creating disk {} ...", slot));
+        info!(
+            "Creating disk {} of {} from snapshot {}.",
+            slot, s_name, snapshot.name
+        );
+
+        let disk_id = client
+            .create_disk(&vm.sub, &vm.rg, &name, &current, &snapshot.id)
+            .await?
+            .timeout(Some(TIMEOUT))
+            .wait()
+            .await?;
+
+        previous.push(attached.name.clone());
+        attached.managed_disk.as_mut().unwrap().id = disk_id;
+        attached.name = name;
+        if attached.lun.is_some() {
+            attached.create_option = Some("Attach".to_owned());
+        }
+    }
+
+    progress!(progress, ctx, "Swapping the disks ...");
+    info!("Swapping the disks of {} to backup {}.", s_name, label);
+
+    client
+        .swap_disks(&vm.sub, &vm.rg, &vm.name, &profile)
+        .await?
+        .timeout(Some(TIMEOUT))
+        .wait()
+        .await?;
+
+    progress!(
+        progress,
+        ctx,
+        format!(
+            "Restored backup `{}`. The previous disks are kept: {}",
+            label,
+            previous.join(", ")
+        )
+    );
+    info!("Successfully restored backup {} of {}.", label, s_name);
+
+    Ok(())
+}
+
+/// Snapshots of the server's backups by label, which sort by creation time.
+async fn list_backups(
+    client: &AzureClient,
+    vm: &VmConfig,
+    s_name: &str,
+) -> SimpleResult<BTreeMap<String, Vec<Snapshot>>> {
+    let snapshots = client.snapshots(&vm.sub, &vm.rg).await?;
+
+    let mut backups: BTreeMap<String, Vec<Snapshot>> = BTreeMap::new();
+    for snapshot in snapshots {
+        if snapshot.tags.get(TAG_SERVER).map(String::as_str) != Some(s_name) {
+            continue;
+        }
+
+        // Snapshots without a disk slot can't be restored
+        if !snapshot.tags.contains_key(TAG_DISK) {
+            continue;
+        }
+
+        if let Some(label) = snapshot.tags.get(TAG_BACKUP).cloned() {
+            backups.entry(label).or_default().push(snapshot);
+        }
+    }
+
+    Ok(backups)
+}
+
+/// Slot of a snapshot from [`list_backups`], which only returns snapshots having one.
+fn disk_slot(snapshot: &Snapshot) -> &str {
+    &snapshot.tags[TAG_DISK]
+}
+
+pub struct BackupPermission(pub String, pub &'static str);
+
+impl BackupPermission {
+    pub fn from_invocation(inv: &Invocation<'_>) -> SimpleResult<Self> {
+        let action = match inv.name {
+            "backup" => "backup",
+            "backups" => "backups",
+            _ => "restore",
+        };
+
+        Ok(BackupPermission(server_name(inv)?.to_owned(), action))
+    }
+}
+
+impl RbacPermission for BackupPermission {
+    type T = String;
+
+    fn rbac(&self) -> String {
+        format!("/{}/{}", self.0, self.1)
+    }
+}
+
+has_permission! { BackupPermission }
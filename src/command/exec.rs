@@ -0,0 +1,218 @@
+use crate::azure::management::vm_run_cmd::RunCommandParameter;
+use crate::command::{
+    inline_output, instance_lock, progress, usage_error, Invocation, ProgressMessage,
+};
+use crate::conf::{ParameterKind, ScriptParameter};
+use crate::job::{Job, JobKind, JobStep};
+use crate::permission::has_permission;
+use crate::permission::rbac::RbacPermission;
+use crate::provider::provider;
+use crate::sql::SqlKey;
+use crate::{AzureClientKey, ConfigKey, SimpleError, SimpleResult, CMD_PREFIX};
+use log::info;
+use serenity::client::Context;
+use serenity::framework::standard::macros::command;
+use serenity::framework::standard::CommandResult;
+use serenity::model::channel::Message;
+use std::fs;
+use std::time::Duration;
+
+const SCRIPT_TIMEOUT: Duration = Duration::from_secs(300);
+
+#[command]
+async fn exec(ctx: &Context, msg: &Message) -> CommandResult {
+    execute(ctx, &Invocation::from_message(msg, "exec")).await?;
+
+    Ok(())
+}
+
+pub async fn execute(ctx: &Context, inv: &Invocation<'_>) -> SimpleResult<()> {
+    let data = ctx.data.read().await;
+
+    let (s_name, action) = target(inv)?;
+
+    let config = data.get::<ConfigKey>().unwrap();
+    let client = data.get::<AzureClientKey>().unwrap();
+
+    let servers = config.servers.snapshot();
+    let server_conf = servers
+        .get(s_name)
+        .ok_or_else(|| usage_error!("Invalid instance."))?;
+
+    let script_conf = server_conf.scripts.get(action).ok_or_else(|| {
+        let mut actions = server_conf.scripts.keys().cloned().collect::<Vec<_>>();
+        actions.sort();
+
+        if actions.is_empty() {
+            usage_error!("No scripts configured for {}.", s_name)
+        } else {
+            usage_error!("Unknown action, available: {}.", actions.join(", "))
+        }
+    })?;
+
+    let parameters = parameters(inv, s_name, action, &script_conf.parameters)?;
+
+    let _l = instance_lock!(data, s_name)?;
+
+    let provider = provider(client, server_conf);
+
+    if !provider.status().await?.ready {
+        return Err(usage_error!("The server is not running."));
+    }
+
+    let mut progress = ProgressMessage::new(inv);
+    let job = Job::begin(
+        data.get::<SqlKey>().unwrap(),
+        s_name,
+        JobKind::Exec,
+        Some(action),
+        progress.channel(),
+        Some(inv.user.id),
+    )?;
+
+    // Errors inside the block still have to finish the job
+    let res: SimpleResult<()> = async {
+        job.step(JobStep::Script);
+        progress!(progress, ctx, format!("Executing {} ...", action));
+        info!("Executing script {} on {}.", action, s_name);
+
+        let script = fs::read_to_string(&script_conf.path)?;
+
+        let output = provider
+            .run_script(&script, parameters, SCRIPT_TIMEOUT)
+            .await?
+            .into_result()?;
+
+        match inline_output(&output) {
+            Some(inline) => {
+                progress!(progress, ctx, format!("Executed {}.{}", action, inline));
+            }
+            None => {
+                progress!(
+                    progress,
+                    ctx,
+                    format!("Executed {}, the output is attached.", action)
+                );
+                inv.respond_with_file(
+                    ctx,
+                    format!("Output of {}:", action),
+                    "output.txt",
+                    output.to_string().into_bytes(),
+                )
+                .await?;
+            }
+        }
+
+        info!("Successfully executed script {} on {}.", action, s_name);
+
+        Ok(())
+    }
+    .await;
+    job.finish(&res);
+
+    res
+}
+
+/// Validates the arguments following the action against the declared parameters.
+fn parameters(
+    inv: &Invocation<'_>,
+    s_name: &str,
+    action: &str,
+    declared: &[ScriptParameter],
+) -> SimpleResult<Vec<RunCommandParameter>> {
+    let values = &inv.args[2..];
+
+    if values.len() != declared.len() {
+        let syntax = declared
+            .iter()
+            .map(|p| format!(" <{}>", p.name))
+            .collect::<String>();
+        return Err(usage_error!(
+            "Syntax: {}exec {} {}{}.",
+            CMD_PREFIX,
+            s_name,
+            action,
+            syntax
+        ));
+    }
+
+    declared
+        .iter()
+        .zip(values)
+        .map(|(param, value)| {
+            validate(param, value)?;
+            Ok(RunCommandParameter {
+                name: param.name.clone(),
+                value: value.clone(),
+            })
+        })
+        .collect()
+}
+
+fn validate(param: &ScriptParameter, value: &str) -> SimpleResult<()> {
+    let valid = match param.kind {
+        ParameterKind::Enum { ref values } => values.iter().any(|v| v == value),
+        ParameterKind::Integer { min, max } => value.parse::<i64>().map_or(false, |i| {
+            min.map_or(true, |min| i >= min) && max.map_or(true, |max| i <= max)
+        }),
+        ParameterKind::Regex { ref pattern } => pattern.is_match(value),
+    };
+
+    if valid {
+        Ok(())
+    } else {
+        Err(usage_error!(
+            "Invalid {}, expected {}.",
+            param.name,
+            describe(param)
+        ))
+    }
+}
+
+fn describe(param: &ScriptParameter) -> String {
+    match param.kind {
+        ParameterKind::Enum { ref values } => format!("one of {}", values.join(", ")),
+        ParameterKind::Integer { min, max } => match (min, max) {
+            (Some(min), Some(max)) => format!("an integer from {} to {}", min, max),
+            (Some(min), None) => format!("an integer of at least {}", min),
+            (None, Some(max)) => format!("an integer of at most {}", max),
+            (None, None) => "an integer".to_owned(),
+        },
+        ParameterKind::Regex { ref pattern } => {
+            // Strip the anchors added when loading the config
+            let pattern = pattern.as_str();
+            format!("a value matching `{}`", &pattern[4..pattern.len() - 2])
+        }
+    }
+}
+
+/// Instance and action of the invocation.
+fn target<'a>(inv: &'a Invocation<'_>) -> SimpleResult<(&'a str, &'a str)> {
+    match inv.args.as_slice() {
+        [s_name, action, ..] => Ok((s_name.as_str(), action.as_str())),
+        _ => Err(syntax_error()),
+    }
+}
+
+fn syntax_error() -> SimpleError {
+    usage_error!("Syntax: {}exec <instance> <action>.", CMD_PREFIX)
+}
+
+pub struct ExecPermission(pub String, pub String);
+
+impl ExecPermission {
+    pub fn from_invocation(inv: &Invocation<'_>) -> SimpleResult<Self> {
+        let (s_name, action) = target(inv)?;
+        Ok(ExecPermission(s_name.to_owned(), action.to_owned()))
+    }
+}
+
+impl RbacPermission for ExecPermission {
+    type T = String;
+
+    fn rbac(&self) -> String {
+        format!("/{}/exec/{}", self.0, self.1)
+    }
+}
+
+has_permission! { ExecPermission }
@@ -0,0 +1,78 @@
+use crate::command::start::StartPermission;
+use crate::command::stop::StopPermission;
+use crate::command::Invocation;
+use crate::permission::check_permission;
+use crate::provider::provider;
+use crate::{AzureClientKey, ConfigKey, SimpleResult};
+use futures::future::join_all;
+use serenity::client::Context;
+use serenity::framework::standard::macros::command;
+use serenity::framework::standard::CommandResult;
+use serenity::model::channel::Message;
+
+#[command]
+async fn list(ctx: &Context, msg: &Message) -> CommandResult {
+    execute(ctx, &Invocation::from_message(msg, "list")).await?;
+
+    Ok(())
+}
+
+pub async fn execute(ctx: &Context, inv: &Invocation<'_>) -> SimpleResult<()> {
+    // Checking permissions reads the data itself, so it mustn't be locked meanwhile
+    let servers = {
+        let data = ctx.data.read().await;
+        let config = data.get::<ConfigKey>().unwrap();
+        config
+            .servers
+            .snapshot()
+            .keys()
+            .cloned()
+            .collect::<Vec<_>>()
+    };
+
+    let mut visible = Vec::new();
+    for s_name in servers {
+        let start = StartPermission(s_name.clone());
+        let stop = StopPermission(s_name.clone());
+
+        if check_permission(ctx, inv.guild_id, inv.user.id, &start).await?
+            || check_permission(ctx, inv.guild_id, inv.user.id, &stop).await?
+        {
+            visible.push(s_name);
+        }
+    }
+
+    visible.sort();
+
+    if visible.is_empty() {
+        inv.respond(ctx, "No servers available.").await?;
+        return Ok(());
+    }
+
+    let data = ctx.data.read().await;
+    let servers = &data.get::<ConfigKey>().unwrap().servers.snapshot();
+    let client = data.get::<AzureClientKey>().unwrap();
+
+    // Servers may have been removed by the discovery meanwhile
+    visible.retain(|s_name| servers.contains_key(s_name));
+
+    let statuses = join_all(
+        visible
+            .iter()
+            .map(|s_name| async move { provider(client, &servers[s_name]).status().await }),
+    )
+    .await;
+
+    let lines = visible
+        .iter()
+        .zip(statuses)
+        .map(|(s_name, status)| match status {
+            Ok(status) => format!("**{}**: {}", s_name, status.power_state),
+            Err(why) => format!("**{}**: unavailable ({})", s_name, why),
+        })
+        .collect::<Vec<_>>();
+
+    inv.respond(ctx, lines.join("\n")).await?;
+
+    Ok(())
+}
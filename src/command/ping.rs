@@ -1,5 +1,7 @@
+use crate::command::Invocation;
 use crate::permission::has_permission;
 use crate::permission::rbac::RbacPermission;
+use crate::SimpleResult;
 use serenity::client::Context;
 use serenity::framework::standard::macros::command;
 use serenity::framework::standard::CommandResult;
@@ -7,7 +9,13 @@ use serenity::model::channel::Message;
 
 #[command]
 async fn ping(ctx: &Context, msg: &Message) -> CommandResult {
-    msg.reply(ctx, "Pong!").await?;
+    execute(ctx, &Invocation::from_message(msg, "ping")).await?;
+
+    Ok(())
+}
+
+pub async fn execute(ctx: &Context, inv: &Invocation<'_>) -> SimpleResult<()> {
+    inv.respond(ctx, "Pong!").await?;
 
     Ok(())
 }
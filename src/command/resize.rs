@@ -0,0 +1,129 @@
+use crate::azure::management::vm::{PowerState, VmClient};
+use crate::command::{
+    instance_lock, progress, server_name, usage_error, Invocation, ProgressMessage,
+};
+use crate::permission::has_permission;
+use crate::permission::rbac::RbacPermission;
+use crate::{AzureClientKey, ConfigKey, SimpleResult};
+use log::info;
+use serenity::client::Context;
+use serenity::framework::standard::macros::command;
+use serenity::framework::standard::CommandResult;
+use serenity::model::channel::Message;
+use std::time::Duration;
+
+const TIMEOUT: Duration = Duration::from_secs(120);
+
+#[command]
+async fn resize(ctx: &Context, msg: &Message) -> CommandResult {
+    execute(ctx, &Invocation::from_message(msg, "resize")).await?;
+
+    Ok(())
+}
+
+pub async fn execute(ctx: &Context, inv: &Invocation<'_>) -> SimpleResult<()> {
+    let data = ctx.data.read().await;
+
+    let s_name = server_name(inv)?;
+
+    let _l = instance_lock!(data, s_name)?;
+
+    let config = data.get::<ConfigKey>().unwrap();
+    let client = data.get::<AzureClientKey>().unwrap();
+
+    let servers = config.servers.snapshot();
+    let server_conf = servers
+        .get(s_name)
+        .ok_or_else(|| usage_error!("Invalid instance."))?;
+
+    let vm = server_conf.vm()?;
+    let current = client.model(&vm.sub, &vm.rg, &vm.name).await?;
+
+    let choices = if server_conf.sizes.is_empty() {
+        "none configured".to_owned()
+    } else {
+        server_conf.sizes.join(", ")
+    };
+
+    let size = match inv.args.get(1) {
+        Some(size) => size,
+        None => {
+            inv.respond(
+                ctx,
+                format!(
+                    "**{}** has size `{}`, allowed sizes: {}",
+                    s_name,
+                    current.size(),
+                    choices
+                ),
+            )
+            .await?;
+            return Ok(());
+        }
+    };
+
+    if !server_conf.sizes.contains(size) {
+        return Err(usage_error!(
+            "Size {} is not allowed for {}, allowed sizes: {}",
+            size,
+            s_name,
+            choices
+        ));
+    }
+
+    if current.size() == size {
+        return Err(usage_error!("{} already has size {}.", s_name, size));
+    }
+
+    let power_state = client
+        .instance_view(&vm.sub, &vm.rg, &vm.name)
+        .await?
+        .power_state();
+
+    if power_state != PowerState::Deallocated {
+        return Err(usage_error!(
+            "The server has to be stopped for resizing, it is {}.",
+            power_state
+        ));
+    }
+
+    let mut progress = ProgressMessage::new(inv);
+
+    progress!(
+        progress,
+        ctx,
+        format!("Resizing from {} to {} ...", current.size(), size)
+    );
+    info!("Resizing {} from {} to {}.", s_name, current.size(), size);
+
+    // Fails unless the update ends in the provisioning state succeeded
+    client
+        .resize(&vm.sub, &vm.rg, &vm.name, size)
+        .await?
+        .timeout(Some(TIMEOUT))
+        .wait()
+        .await?;
+
+    progress!(progress, ctx, format!("Resized the server to {}.", size));
+    info!("Successfully resized {} to {}.", s_name, size);
+
+    Ok(())
+}
+
+pub struct ResizePermission(pub String);
+
+impl ResizePermission {
+    pub fn from_invocation(inv: &Invocation<'_>) -> SimpleResult<Self> {
+        Ok(ResizePermission(server_name(inv)?.to_owned()))
+    }
+}
+
+impl RbacPermission for ResizePermission {
+    type T = String;
+
+    fn rbac(&self) -> String {
+        format!("/{}/resize", self.0)
+    }
+}
+
+has_permission! { ResizePermission }
@@ -0,0 +1,119 @@
+use crate::azure::management::vm::PowerState;
+use crate::azure::management::vm_run_cmd::ScriptOutput;
+use crate::command::start::wait_for_agent;
+use crate::command::{
+    instance_lock, progress, read_script, server_name, stop_on_timeout, usage_error, Invocation,
+    ProgressMessage,
+};
+use crate::permission::has_permission;
+use crate::permission::rbac::RbacPermission;
+use crate::provider::provider;
+use crate::{AzureClientKey, ConfigKey, SimpleError, SimpleResult};
+use log::{info, warn};
+use serenity::client::Context;
+use serenity::framework::standard::macros::command;
+use serenity::framework::standard::CommandResult;
+use serenity::model::channel::Message;
+use std::time::Duration;
+
+const TIMEOUT: Duration = Duration::from_secs(300);
+const SCRIPT_TIMEOUT: Duration = Duration::from_secs(300);
+
+#[command]
+async fn restart(ctx: &Context, msg: &Message) -> CommandResult {
+    execute(ctx, &Invocation::from_message(msg, "restart")).await?;
+
+    Ok(())
+}
+
+pub async fn execute(ctx: &Context, inv: &Invocation<'_>) -> SimpleResult<()> {
+    let data = ctx.data.read().await;
+
+    let s_name = server_name(inv)?;
+
+    let _l = instance_lock!(data, s_name)?;
+
+    let config = data.get::<ConfigKey>().unwrap();
+    let client = data.get::<AzureClientKey>().unwrap();
+
+    let servers = config.servers.snapshot();
+    let server_conf = servers
+        .get(s_name)
+        .ok_or_else(|| usage_error!("Invalid instance."))?;
+
+    let provider = provider(client, server_conf);
+
+    // The stop script can only be run on a running server
+    if provider.status().await?.power_state != PowerState::Running {
+        return Err(usage_error!("{} isn't running, start it instead.", s_name));
+    }
+
+    // Read up front, so a missing script doesn't leave the server stopped
+    let stop_script = read_script(server_conf.stop_script.as_deref(), "stop")?;
+    let start_script = read_script(server_conf.start_script.as_deref(), "start")?;
+
+    let mut progress = ProgressMessage::new(inv);
+
+    progress!(progress, ctx, "Executing stop script ...");
+    info!("Executing stop script on {}.", s_name);
+
+    provider
+        .run_script(&stop_script, Vec::new(), SCRIPT_TIMEOUT)
+        .await
+        .and_then(ScriptOutput::into_result)
+        .map(|_| ())
+        .or_else(|e| match e {
+            SimpleError::Timeout => {
+                warn!("Failed to shutdown server gracefully: {:?}", e);
+                Ok(())
+            }
+            other => Err(other),
+        })?;
+
+    progress!(progress, ctx, "Restarting the server ...");
+    info!("Restarting instance {}.", s_name);
+
+    let restart_res = provider.restart(TIMEOUT).await;
+
+    stop_on_timeout!(restart_res, provider)?;
+
+    progress!(progress, ctx, "Server restarted. Waiting for agent ...");
+    info!("Successfully restarted {}, waiting for agent.", s_name);
+
+    let ready = wait_for_agent(&*provider).await;
+
+    stop_on_timeout!(ready, provider)?;
+
+    progress!(progress, ctx, "Executing start script ...");
+    info!("Executing start script on {}.", s_name);
+
+    let run_res = provider
+        .run_script(&start_script, Vec::new(), SCRIPT_TIMEOUT)
+        .await
+        .and_then(ScriptOutput::into_result);
+
+    stop_on_timeout!(run_res, provider)?;
+
+    progress!(progress, ctx, "Restarted the server.");
+    info!("Successfully restarted {}.", s_name);
+
+    Ok(())
+}
+
+pub struct RestartPermission(pub String);
+
+impl RestartPermission {
+    pub fn from_invocation(inv: &Invocation<'_>) -> SimpleResult<Self> {
+        Ok(RestartPermission(server_name(inv)?.to_owned()))
+    }
+}
+
+impl RbacPermission for RestartPermission {
+    type T = String;
+
+    fn rbac(&self) -> String {
+        format!("/{}/restart", self.0)
+    }
+}
+
+has_permission! { RestartPermission }
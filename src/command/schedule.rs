@@ -0,0 +1,144 @@
+use crate::command::{usage_error, Invocation};
+use crate::permission::has_permission;
+use crate::permission::rbac::RbacPermission;
+use crate::schedule::{next_run, ScheduledAction};
+use crate::sql::schedule::{paused, resume, NewSchedulePause};
+use crate::sql::SqlKey;
+use crate::{ConfigKey, SimpleError, SimpleResult, CMD_PREFIX};
+use chrono::Utc;
+use serenity::client::Context;
+use serenity::framework::standard::macros::command;
+use serenity::framework::standard::CommandResult;
+use serenity::model::channel::Message;
+
+#[command]
+async fn schedule(ctx: &Context, msg: &Message) -> CommandResult {
+    execute(ctx, &Invocation::from_message(msg, "schedule")).await?;
+
+    Ok(())
+}
+
+pub async fn execute(ctx: &Context, inv: &Invocation<'_>) -> SimpleResult<()> {
+    let data = ctx.data.read().await;
+
+    let servers = data.get::<ConfigKey>().unwrap().servers.snapshot();
+    let mut sql = data.get::<SqlKey>().unwrap().connection.get()?;
+
+    let args = inv.args.iter().map(String::as_str).collect::<Vec<_>>();
+
+    let reply = match args.as_slice() {
+        [] | ["list"] => {
+            let paused = paused(&mut sql)?;
+            let now = Utc::now();
+
+            let mut scheduled = servers
+                .iter()
+                .filter_map(|(s_name, c)| c.schedule.as_ref().map(|s| (s_name, c, s)))
+                .collect::<Vec<_>>();
+            scheduled.sort_by_key(|(s_name, _, _)| *s_name);
+
+            if scheduled.is_empty() {
+                "No schedules configured.".to_owned()
+            } else {
+                scheduled
+                    .into_iter()
+                    .map(|(s_name, server_conf, schedule)| {
+                        let mut lines = vec![format!(
+                            "**{}** ({}){}",
+                            s_name,
+                            schedule.timezone,
+                            if paused.contains(s_name) {
+                                ", paused"
+                            } else {
+                                ""
+                            }
+                        )];
+
+                        for (action, cron) in [
+                            (ScheduledAction::Start, &schedule.start),
+                            (ScheduledAction::Stop, &schedule.stop),
+                        ] {
+                            if let Some(cron) = cron {
+                                let next = next_run(server_conf, action, now)
+                                    .map_or("never".to_owned(), |t| {
+                                        format!("<t:{}:f>", t.timestamp())
+                                    });
+                                lines.push(format!("{}: `{}`, next: {}", action, cron, next));
+                            }
+                        }
+
+                        lines.join("\n")
+                    })
+                    .collect::<Vec<_>>()
+                    .join("\n\n")
+            }
+        }
+        ["pause", s_name] => {
+            servers
+                .get(*s_name)
+                .and_then(|c| c.schedule.as_ref())
+                .ok_or_else(|| usage_error!("No schedule configured for this instance."))?;
+
+            let pause = NewSchedulePause {
+                server: s_name,
+                paused_by: inv.user.id.0 as i64,
+                paused_at: Utc::now().naive_utc(),
+            };
+
+            if pause.insert(&mut sql)? {
+                format!("Paused the schedule of {}.", s_name)
+            } else {
+                format!("The schedule of {} is already paused.", s_name)
+            }
+        }
+        ["resume", s_name] => {
+            if resume(&mut sql, s_name)? {
+                format!("Resumed the schedule of {}.", s_name)
+            } else {
+                format!("The schedule of {} isn't paused.", s_name)
+            }
+        }
+        _ => return Err(syntax_error()),
+    };
+
+    inv.respond(ctx, reply).await?;
+
+    Ok(())
+}
+
+fn syntax_error() -> SimpleError {
+    usage_error!(
+        "Syntax: {}schedule [list | pause <instance> | resume <instance>].",
+        CMD_PREFIX
+    )
+}
+
+pub enum SchedulePermission {
+    List,
+    Modify(String),
+}
+
+impl SchedulePermission {
+    pub fn from_invocation(inv: &Invocation<'_>) -> SimpleResult<Self> {
+        let args = inv.args.iter().map(String::as_str).collect::<Vec<_>>();
+
+        match args.as_slice() {
+            [] | ["list"] => Ok(SchedulePermission::List),
+            ["pause" | "resume", s_name] => Ok(SchedulePermission::Modify((*s_name).to_owned())),
+            _ => Err(syntax_error()),
+        }
+    }
+}
+
+impl RbacPermission for SchedulePermission {
+    type T = String;
+
+    fn rbac(&self) -> String {
+        match self {
+            SchedulePermission::List => "/schedule".to_owned(),
+            SchedulePermission::Modify(s_name) => format!("/{}/schedule", s_name),
+        }
+    }
+}
+
+has_permission! { SchedulePermission }
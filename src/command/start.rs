@@ -1,15 +1,27 @@
-use crate::azure::management::vm::VmClient;
-use crate::azure::management::vm_run_cmd::{ShellCommand, VmRunCmdClient};
-use crate::command::{instance_lock, progress, stop_on_timeout, usage_error, ProgressMessage};
+use crate::azure::management::network::NetworkClient;
+use crate::azure::management::vm_run_cmd::ScriptOutput;
+use crate::azure::AzureClient;
+use crate::budget::{describe_remaining, used_hours};
+use crate::command::usage::current_month;
+use crate::command::{
+    cancellable, instance_lock, progress, read_script, server_name, stop_on_timeout, usage_error,
+    CancelKey, CancelToken, Invocation, ProgressButton, ProgressMessage,
+};
+use crate::conf::{ProviderConfig, ServerConfig};
+use crate::dependency::start_dependencies;
+use crate::job::{Job, JobKind, JobStep};
+use crate::owners::Owners;
 use crate::permission::has_permission;
 use crate::permission::rbac::RbacPermission;
-use crate::{AzureClientKey, ConfigKey, SimpleError, SimpleResult, CMD_PREFIX};
-use log::info;
+use crate::provider::{provider, ComputeProvider};
+use crate::quota::check_quota;
+use crate::sql::SqlKey;
+use crate::{AzureClientKey, ConfigKey, SimpleError, SimpleResult};
+use log::{info, warn};
 use serenity::client::Context;
 use serenity::framework::standard::macros::command;
 use serenity::framework::standard::CommandResult;
 use serenity::model::channel::Message;
-use std::fs;
 use std::time::{Duration, SystemTime};
 use tokio::time::sleep;
 
@@ -18,136 +30,243 @@ const SCRIPT_TIMEOUT: Duration = Duration::from_secs(300);
 
 #[command]
 async fn start(ctx: &Context, msg: &Message) -> CommandResult {
+    execute(ctx, &Invocation::from_message(msg, "start")).await?;
+
+    Ok(())
+}
+
+pub async fn execute(ctx: &Context, inv: &Invocation<'_>) -> SimpleResult<()> {
     let data = ctx.data.read().await;
 
-    let s_name = server_name(msg)?;
+    let s_name = server_name(inv)?;
 
     let _l = instance_lock!(data, s_name)?;
 
     let config = data.get::<ConfigKey>().unwrap();
     let client = data.get::<AzureClientKey>().unwrap();
 
-    let server_conf = config
-        .servers
+    let servers = config.servers.snapshot();
+    let server_conf = servers
         .get(s_name)
         .ok_or_else(|| usage_error!("Invalid instance."))?;
 
-    let mut progress = ProgressMessage::new(msg);
+    check_quota(ctx, &data, inv, s_name).await?;
 
-    progress!(progress, ctx, "Booting the server ...");
-    info!("Booting instance {}.", s_name);
+    let sql = data.get::<SqlKey>().unwrap();
+    let mut progress = ProgressMessage::new(inv);
 
-    // Booting the server
-    let start_res = client
-        .start(
-            &server_conf.vm.sub,
-            &server_conf.vm.rg,
-            &server_conf.vm.name,
-        )
-        .await?
-        .timeout(Some(TIMEOUT))
-        .wait()
-        .await;
+    if let Some(ref budget) = server_conf.budget {
+        let used = used_hours(sql, s_name, current_month())?;
 
-    stop_on_timeout!(
-        start_res,
-        client,
-        &server_conf.vm.sub,
-        &server_conf.vm.rg,
-        &server_conf.vm.name
+        // Owners may exceed the budget, everybody else has to ask them
+        if used >= budget.hours && !data.get::<Owners>().unwrap().contains(&inv.user.id) {
+            return Err(usage_error!(
+                "The monthly budget of {} is used up, ask an owner to start it.",
+                s_name
+            ));
+        }
+
+        progress.set_header(format!(
+            "Remaining budget: {}",
+            describe_remaining(budget, used)
+        ));
+    }
+
+    let cancel = data.get::<CancelKey>().unwrap().register(s_name);
+
+    start_dependencies(
+        ctx,
+        &data,
+        s_name,
+        &mut progress,
+        &cancel,
+        Some(inv.user.id),
+    )
+    .await?;
+
+    let job = Job::begin(
+        sql,
+        s_name,
+        JobKind::Start,
+        None,
+        progress.channel(),
+        Some(inv.user.id),
     )?;
 
-    progress!(progress, ctx, "Server booted. Waiting for agent ...");
-    info!("Successfully booted {}, waiting for agent.", s_name);
+    start_server(
+        ctx,
+        client,
+        s_name,
+        server_conf,
+        &mut progress,
+        &cancel,
+        &job,
+    )
+    .await?;
 
-    // Waiting for server to be ready, or timeout after 120 seconds
-    let loop_start = SystemTime::now();
+    Ok(())
+}
 
-    let ready = loop {
-        if SystemTime::now().duration_since(loop_start).unwrap() > TIMEOUT {
-            break Err(SimpleError::Timeout);
+/// Connection address formatted from the configured template, None if there is none or the lookup failed.
+///
+/// Only vms have a public ip, the template of other servers is used as is.
+async fn connection_address(
+    client: &AzureClient,
+    s_name: &str,
+    server_conf: &ServerConfig,
+) -> Option<String> {
+    let template = server_conf.address.as_ref()?;
+
+    let vm = match server_conf.provider {
+        ProviderConfig::Vm(ref vm) => vm,
+        ProviderConfig::Local(_) => return Some(template.clone()),
+    };
+
+    let addresses = client.public_addresses(&vm.sub, &vm.rg, &vm.name).await;
+
+    match addresses {
+        Ok(addresses) => addresses.first().map(|a| a.format(template)),
+        Err(why) => {
+            warn!("Error fetching the public address of {}: {:?}", s_name, why);
+            None
         }
+    }
+}
 
-        let instance = client
-            .instance_view(
-                &server_conf.vm.sub,
-                &server_conf.vm.rg,
-                &server_conf.vm.name,
-            )
-            .await?;
-        if instance
-            .vm_agent
-            .map(|agent| {
-                agent
-                    .statuses
-                    .get(0)
-                    .map_or(false, |s| s.display_status == "Ready")
-            })
-            .unwrap_or(false)
-        {
-            break Ok(());
+/// Boots the server and executes the start script, the caller must hold the instance lock.
+///
+/// Cancelling the token aborts the start and powers the server off, the job is finished afterwards.
+pub async fn start_server(
+    ctx: &Context,
+    client: &AzureClient,
+    s_name: &str,
+    server_conf: &ServerConfig,
+    progress: &mut ProgressMessage<'_>,
+    cancel: &CancelToken,
+    job: &Job,
+) -> SimpleResult<()> {
+    progress.set_buttons(s_name, &[ProgressButton::Cancel]);
+
+    let provider = provider(client, server_conf);
+    let res = boot(ctx, &*provider, s_name, server_conf, progress, cancel, job).await;
+    job.finish(&res);
+
+    // The server has been powered off again
+    if matches!(res, Err(SimpleError::Timeout | SimpleError::Cancelled)) {
+        job.vm_stopped();
+    }
+
+    match res {
+        Ok(()) => {
+            progress.set_buttons(s_name, &[ProgressButton::Stop, ProgressButton::Status]);
+            let message = match connection_address(client, s_name, server_conf).await {
+                Some(address) => format!("Started the server. Connect to `{}`.", address),
+                None => "Started the server.".to_owned(),
+            };
+            progress!(progress, ctx, message);
+            info!("Successfully started {}.", s_name);
+        }
+        Err(SimpleError::Cancelled) => {
+            progress.set_buttons(s_name, &[]);
+            progress!(progress, ctx, "Cancelled, the server has been powered off.");
+            info!("Cancelled the start of {}.", s_name);
+        }
+        Err(_) => {
+            progress.set_buttons(s_name, &[]);
+            tri!(
+                progress.refresh(ctx).await,
+                "Error removing buttons from progress message"
+            );
         }
+    }
 
-        sleep(Duration::from_secs(10)).await;
-    };
+    res
+}
 
-    stop_on_timeout!(
-        ready,
-        client,
-        &server_conf.vm.sub,
-        &server_conf.vm.rg,
-        &server_conf.vm.name
-    )?;
+async fn boot(
+    ctx: &Context,
+    provider: &dyn ComputeProvider,
+    s_name: &str,
+    server_conf: &ServerConfig,
+    progress: &mut ProgressMessage<'_>,
+    cancel: &CancelToken,
+    job: &Job,
+) -> SimpleResult<()> {
+    job.step(JobStep::Boot);
+    progress!(progress, ctx, "Booting the server ...");
+    info!("Booting instance {}.", s_name);
 
-    let file = fs::read(&server_conf.start_script.as_ref().unwrap())?;
-    let script = ShellCommand {
-        script: [std::str::from_utf8(&file).unwrap()],
-    };
+    // Booting the server
+    let start_res = cancellable!(cancel, provider.power_on(TIMEOUT));
+
+    stop_on_timeout!(start_res, provider)?;
+
+    job.vm_started();
+
+    job.step(JobStep::WaitForAgent);
+    progress!(progress, ctx, "Server booted. Waiting for agent ...");
+    info!("Successfully booted {}, waiting for agent.", s_name);
 
+    // Waiting for server to be ready, or timeout after 120 seconds
+    let ready = cancellable!(cancel, wait_for_agent(provider));
+
+    stop_on_timeout!(ready, provider)?;
+
+    let script = read_script(server_conf.start_script.as_deref(), "start")?;
+
+    job.step(JobStep::StartScript);
     progress!(progress, ctx, "Executing start script ...");
     info!("Executing start script on {}.", s_name);
 
     // Fire start command for game server
-    let run_res = client
-        .run(
-            &server_conf.vm.sub,
-            &server_conf.vm.rg,
-            &server_conf.vm.name,
-            script,
-        )
-        .await?
-        .timeout(Some(SCRIPT_TIMEOUT))
-        .wait()
-        .await;
+    let run_res = cancellable!(
+        cancel,
+        provider.run_script(&script, Vec::new(), SCRIPT_TIMEOUT)
+    )
+    .and_then(ScriptOutput::into_result);
 
-    stop_on_timeout!(
-        run_res,
-        client,
-        &server_conf.vm.sub,
-        &server_conf.vm.rg,
-        &server_conf.vm.name
-    )?;
+    stop_on_timeout!(run_res, provider)?;
+
+    Ok(())
+}
+
+/// Cancels a running start, invoked by the button on its progress message.
+pub async fn cancel(ctx: &Context, inv: &Invocation<'_>) -> SimpleResult<()> {
+    let data = ctx.data.read().await;
 
-    progress!(progress, ctx, "Started the server.");
-    info!("Successfully started {}.", s_name);
+    let s_name = server_name(inv)?;
+
+    if !data.get::<CancelKey>().unwrap().cancel(s_name) {
+        return Err(usage_error!("There is nothing to cancel."));
+    }
+
+    inv.respond(ctx, format!("Cancelling the start of {} ...", s_name))
+        .await?;
 
     Ok(())
 }
 
-fn server_name(msg: &Message) -> SimpleResult<&str> {
-    let offset = CMD_PREFIX.len() + "start".len() + 1;
-    if offset < msg.content.len() {
-        Ok(&msg.content[offset..])
-    } else {
-        Err(usage_error!("Syntax: {}start <instance>.", CMD_PREFIX,))
+pub async fn wait_for_agent(provider: &dyn ComputeProvider) -> SimpleResult<()> {
+    let loop_start = SystemTime::now();
+
+    loop {
+        if SystemTime::now().duration_since(loop_start).unwrap() > TIMEOUT {
+            return Err(SimpleError::Timeout);
+        }
+
+        if provider.status().await?.ready {
+            return Ok(());
+        }
+
+        sleep(Duration::from_secs(10)).await;
     }
 }
 
-pub struct StartPermission(String);
+pub struct StartPermission(pub String);
 
 impl StartPermission {
-    pub fn from_message(msg: &Message) -> SimpleResult<Self> {
-        Ok(StartPermission(server_name(msg)?.to_owned()))
+    pub fn from_invocation(inv: &Invocation<'_>) -> SimpleResult<Self> {
+        Ok(StartPermission(server_name(inv)?.to_owned()))
     }
 }
 
@@ -160,3 +279,98 @@ impl RbacPermission for StartPermission {
 }
 
 has_permission! { StartPermission }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::azure::fake::{FakeAzure, START};
+    use crate::sql::usage::is_open;
+    use crate::testing::{assert_requests, operation, Fixture, S_NAME};
+    use http::StatusCode;
+
+    async fn start(fake: &FakeAzure, cancel: &CancelToken) -> (Fixture, SimpleResult<()>) {
+        let fixture = Fixture::new(fake);
+        let job = fixture.job(JobKind::Start);
+
+        let res = start_server(
+            &fixture.ctx,
+            &fixture.client,
+            S_NAME,
+            &fixture.server_conf,
+            &mut ProgressMessage::silent(),
+            cancel,
+            &job,
+        )
+        .await;
+
+        (fixture, res)
+    }
+
+    fn run_open(fixture: &Fixture) -> bool {
+        is_open(&mut fixture.sql.connection.get().unwrap(), S_NAME).unwrap()
+    }
+
+    #[tokio::test]
+    async fn starts_deallocated_vm() {
+        let fake = FakeAzure::start("deallocated").await;
+
+        let (fixture, res) = start(&fake, &CancelToken::default()).await;
+
+        assert!(res.is_ok(), "{:?}", res);
+        assert_eq!(fake.power_state(), "running");
+        assert_requests(
+            &fake,
+            &[
+                &["POST token"],
+                &operation("POST start"),
+                &["GET instanceView"],
+                &operation("POST runCommand"),
+            ],
+        );
+        assert_eq!(fixture.jobs("succeeded"), 1);
+        assert!(run_open(&fixture));
+    }
+
+    #[tokio::test]
+    async fn failing_start_script_keeps_vm_running() {
+        let fake = FakeAzure::start("deallocated").await;
+        fake.script_exit(3);
+
+        let (fixture, res) = start(&fake, &CancelToken::default()).await;
+
+        match res {
+            Err(SimpleError::ScriptFailed(output)) => assert_eq!(output.exit_status, Some(3)),
+            other => panic!("Expected a failed script, got {:?}", other),
+        }
+        assert_eq!(fake.power_state(), "running");
+        assert_eq!(fixture.jobs("failed"), 1);
+        assert!(run_open(&fixture));
+    }
+
+    #[tokio::test]
+    async fn rejected_start_fails_job() {
+        let fake = FakeAzure::start("deallocated").await;
+        fake.fail(START, StatusCode::CONFLICT);
+
+        let (fixture, res) = start(&fake, &CancelToken::default()).await;
+
+        assert!(res.is_err());
+        assert_eq!(fake.power_state(), "deallocated");
+        assert_eq!(fixture.jobs("failed"), 1);
+        assert!(!run_open(&fixture));
+    }
+
+    #[tokio::test]
+    async fn cancelled_start_powers_vm_off() {
+        let fake = FakeAzure::start("deallocated").await;
+        let cancel = CancelToken::default();
+        cancel.cancel();
+
+        let (fixture, res) = start(&fake, &cancel).await;
+
+        assert!(matches!(res, Err(SimpleError::Cancelled)), "{:?}", res);
+        assert_eq!(fake.power_state(), "deallocated");
+        assert!(fake.requests().contains(&"POST deallocate".to_owned()));
+        assert_eq!(fixture.jobs("failed"), 1);
+    }
+}
@@ -0,0 +1,77 @@
+use crate::command::{server_name, usage_error, Invocation};
+use crate::permission::has_permission;
+use crate::permission::rbac::RbacPermission;
+use crate::provider::{provider, ServerStatus};
+use crate::{AzureClientKey, ConfigKey, SimpleResult};
+use serenity::client::Context;
+use serenity::framework::standard::macros::command;
+use serenity::framework::standard::CommandResult;
+use serenity::model::channel::Message;
+
+#[command]
+async fn status(ctx: &Context, msg: &Message) -> CommandResult {
+    execute(ctx, &Invocation::from_message(msg, "status")).await?;
+
+    Ok(())
+}
+
+pub async fn execute(ctx: &Context, inv: &Invocation<'_>) -> SimpleResult<()> {
+    let data = ctx.data.read().await;
+
+    let s_name = server_name(inv)?;
+
+    let config = data.get::<ConfigKey>().unwrap();
+    let client = data.get::<AzureClientKey>().unwrap();
+
+    let servers = config.servers.snapshot();
+    let server_conf = servers
+        .get(s_name)
+        .ok_or_else(|| usage_error!("Invalid instance."))?;
+
+    let status = provider(client, server_conf).status().await?;
+
+    inv.respond(ctx, status_message(s_name, &status)).await?;
+
+    Ok(())
+}
+
+pub fn status_message(s_name: &str, status: &ServerStatus) -> String {
+    let mut lines = vec![
+        format!("**{}**", s_name),
+        format!("Power state: {}", status.power_state),
+    ];
+    lines.extend(status.details.iter().cloned());
+
+    lines.join("\n")
+}
+
+pub fn format_duration(d: chrono::Duration) -> String {
+    let minutes = d.num_minutes().max(0);
+    let (days, hours, minutes) = (minutes / 1440, minutes / 60 % 24, minutes % 60);
+
+    if days > 0 {
+        format!("{}d {}h {}m", days, hours, minutes)
+    } else if hours > 0 {
+        format!("{}h {}m", hours, minutes)
+    } else {
+        format!("{}m", minutes)
+    }
+}
+
+pub struct StatusPermission(pub String);
+
+impl StatusPermission {
+    pub fn from_invocation(inv: &Invocation<'_>) -> SimpleResult<Self> {
+        Ok(StatusPermission(server_name(inv)?.to_owned()))
+    }
+}
+
+impl RbacPermission for StatusPermission {
+    type T = String;
+
+    fn rbac(&self) -> String {
+        format!("/{}/status", self.0)
+    }
+}
+
+has_permission! { StatusPermission }
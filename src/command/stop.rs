@@ -1,58 +1,106 @@
-use crate::azure::management::vm::VmClient;
-use crate::azure::management::vm_run_cmd::{ShellCommand, VmRunCmdClient};
-use crate::command::{instance_lock, progress, usage_error, ProgressMessage};
+use crate::azure::management::vm_run_cmd::ScriptOutput;
+use crate::azure::AzureClient;
+use crate::command::{
+    instance_lock, progress, read_script, server_name, usage_error, Invocation, ProgressMessage,
+};
+use crate::conf::ServerConfig;
+use crate::dependency::{running_dependents, stop_with_dependencies};
+use crate::job::{Job, JobStep};
 use crate::permission::has_permission;
 use crate::permission::rbac::RbacPermission;
-use crate::{AzureClientKey, ConfigKey, SimpleError, SimpleResult, CMD_PREFIX};
+use crate::provider::{provider, ComputeProvider};
+use crate::{AzureClientKey, ConfigKey, SimpleError, SimpleResult};
 use log::{info, warn};
 use serenity::client::Context;
 use serenity::framework::standard::macros::command;
 use serenity::framework::standard::CommandResult;
 use serenity::model::channel::Message;
-use std::fs;
 use std::time::Duration;
 
 const TIMEOUT: Duration = Duration::from_secs(300);
 
 #[command]
 async fn stop(ctx: &Context, msg: &Message) -> CommandResult {
+    execute(ctx, &Invocation::from_message(msg, "stop")).await?;
+
+    Ok(())
+}
+
+pub async fn execute(ctx: &Context, inv: &Invocation<'_>) -> SimpleResult<()> {
     let data = ctx.data.read().await;
 
-    let s_name = server_name(msg)?;
+    let s_name = server_name(inv)?;
 
     let _l = instance_lock!(data, s_name)?;
 
     let config = data.get::<ConfigKey>().unwrap();
     let client = data.get::<AzureClientKey>().unwrap();
 
-    let mut progress_message = ProgressMessage::new(msg);
-
-    let server_conf = config
-        .servers
+    let servers = config.servers.snapshot();
+    let server_conf = servers
         .get(s_name)
         .ok_or_else(|| usage_error!("Invalid instance."))?;
 
+    let needed_by = running_dependents(client, &servers, s_name).await?;
+    if !needed_by.is_empty() {
+        return Err(usage_error!(
+            "{} is needed by {}, stop them first.",
+            s_name,
+            needed_by.join(", ")
+        ));
+    }
+
+    let mut progress_message = ProgressMessage::new(inv);
+    stop_with_dependencies(
+        ctx,
+        &data,
+        s_name,
+        server_conf,
+        &mut progress_message,
+        Some(inv.user.id),
+    )
+    .await?;
+
+    Ok(())
+}
+
+/// Executes the stop script and powers the server off, the caller must hold the instance lock.
+///
+/// The job is finished afterwards.
+pub async fn stop_server(
+    ctx: &Context,
+    client: &AzureClient,
+    s_name: &str,
+    server_conf: &ServerConfig,
+    progress_message: &mut ProgressMessage<'_>,
+    job: &Job,
+) -> SimpleResult<()> {
+    let provider = provider(client, server_conf);
+    let res = shutdown(ctx, &*provider, s_name, server_conf, progress_message, job).await;
+    job.finish(&res);
+    res
+}
+
+async fn shutdown(
+    ctx: &Context,
+    provider: &dyn ComputeProvider,
+    s_name: &str,
+    server_conf: &ServerConfig,
+    progress_message: &mut ProgressMessage<'_>,
+    job: &Job,
+) -> SimpleResult<()> {
+    job.step(JobStep::StopScript);
     progress!(progress_message, ctx, "Executing stop script  ...");
     info!("Executing stop script on {}.", s_name);
 
-    let file = fs::read(&server_conf.stop_script.as_ref().unwrap())?;
-    let script = ShellCommand {
-        script: [std::str::from_utf8(&file).unwrap()],
-    };
+    let script = read_script(server_conf.stop_script.as_deref(), "stop")?;
 
     let mut force = false;
 
-    client
-        .run(
-            &server_conf.vm.sub,
-            &server_conf.vm.rg,
-            &server_conf.vm.name,
-            script,
-        )
-        .await?
-        .timeout(Some(TIMEOUT))
-        .wait()
+    provider
+        .run_script(&script, Vec::new(), TIMEOUT)
         .await
+        .and_then(ScriptOutput::into_result)
         .map(|_| ())
         .or_else(|e| match e {
             SimpleError::Timeout => {
@@ -63,18 +111,13 @@ async fn stop(ctx: &Context, msg: &Message) -> CommandResult {
             other => Err(other),
         })?;
 
-    progress!(progress_message, ctx, "Deallocating server ...");
-    info!("Deallocating instance {}.", s_name);
+    job.step(JobStep::Deallocate);
+    progress!(progress_message, ctx, "Powering off the server ...");
+    info!("Powering off instance {}.", s_name);
 
-    client
-        .deallocate(
-            &server_conf.vm.sub,
-            &server_conf.vm.rg,
-            &server_conf.vm.name,
-        )
-        .await?
-        .wait()
-        .await?;
+    provider.power_off().await?;
+
+    job.vm_stopped();
 
     if !force {
         progress!(progress_message, ctx, "Stopped the server.");
@@ -87,20 +130,11 @@ async fn stop(ctx: &Context, msg: &Message) -> CommandResult {
     Ok(())
 }
 
-fn server_name(msg: &Message) -> SimpleResult<&str> {
-    let offset = CMD_PREFIX.len() + "stop".len() + 1;
-    if offset < msg.content.len() {
-        Ok(&msg.content[offset..])
-    } else {
-        Err(usage_error!("Syntax: {}stop <instance>.", CMD_PREFIX,))
-    }
-}
-
-pub struct StopPermission(String);
+pub struct StopPermission(pub String);
 
 impl StopPermission {
-    pub fn from_message(msg: &Message) -> SimpleResult<Self> {
-        Ok(StopPermission(server_name(msg)?.to_owned()))
+    pub fn from_invocation(inv: &Invocation<'_>) -> SimpleResult<Self> {
+        Ok(StopPermission(server_name(inv)?.to_owned()))
     }
 }
 
@@ -113,3 +147,61 @@ impl RbacPermission for StopPermission {
 }
 
 has_permission! { StopPermission }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::azure::fake::{FakeAzure, OPERATION};
+    use crate::job::JobKind;
+    use crate::testing::{assert_requests, operation, Fixture, S_NAME};
+    use http::StatusCode;
+
+    async fn stop(fake: &FakeAzure) -> (Fixture, SimpleResult<()>) {
+        let fixture = Fixture::new(fake);
+        let job = fixture.job(JobKind::Stop);
+
+        let res = stop_server(
+            &fixture.ctx,
+            &fixture.client,
+            S_NAME,
+            &fixture.server_conf,
+            &mut ProgressMessage::silent(),
+            &job,
+        )
+        .await;
+
+        (fixture, res)
+    }
+
+    #[tokio::test]
+    async fn stops_running_vm() {
+        let fake = FakeAzure::start("running").await;
+
+        let (fixture, res) = stop(&fake).await;
+
+        assert!(res.is_ok(), "{:?}", res);
+        assert_eq!(fake.power_state(), "deallocated");
+        assert_requests(
+            &fake,
+            &[
+                &["POST token"],
+                &operation("POST runCommand"),
+                &operation("POST deallocate"),
+            ],
+        );
+        assert_eq!(fixture.jobs("succeeded"), 1);
+    }
+
+    #[tokio::test]
+    async fn failing_operation_fails_job() {
+        let fake = FakeAzure::start("running").await;
+        fake.fail(OPERATION, StatusCode::INTERNAL_SERVER_ERROR);
+
+        let (fixture, res) = stop(&fake).await;
+
+        assert!(res.is_err());
+        assert_eq!(fake.power_state(), "running");
+        assert!(!fake.requests().contains(&"POST deallocate".to_owned()));
+        assert_eq!(fixture.jobs("failed"), 1);
+    }
+}
@@ -0,0 +1,219 @@
+use crate::azure::management::vm::PowerState;
+use crate::azure::AzureClient;
+use crate::command::{usage_error, InstanceLockKey, InstanceLocks, Invocation, OMITTED_ARG};
+use crate::conf::ServerConfig;
+use crate::permission::has_permission;
+use crate::permission::rbac::RbacPermission;
+use crate::provider::provider;
+use crate::sql::usage::{close, is_open, runs_between, NewVmRun, VmRun};
+use crate::sql::uuid::Uuid;
+use crate::sql::{Sql, SqlKey};
+use crate::{AzureClientKey, ConfigKey, SimpleError, SimpleResult, CMD_PREFIX};
+use chrono::{Datelike, Duration, NaiveDate, NaiveDateTime, Utc};
+use log::info;
+use serenity::client::Context;
+use serenity::framework::standard::macros::command;
+use serenity::framework::standard::CommandResult;
+use serenity::model::channel::Message;
+use serenity::model::id::UserId;
+use std::collections::BTreeMap;
+
+#[command]
+async fn usage(ctx: &Context, msg: &Message) -> CommandResult {
+    execute(ctx, &Invocation::from_message(msg, "usage")).await?;
+
+    Ok(())
+}
+
+pub async fn execute(ctx: &Context, inv: &Invocation<'_>) -> SimpleResult<()> {
+    let data = ctx.data.read().await;
+
+    let known = data.get::<ConfigKey>().unwrap().servers.snapshot();
+    let client = data.get::<AzureClientKey>().unwrap();
+    let locks = data.get::<InstanceLockKey>().unwrap();
+    let sql = data.get::<SqlKey>().unwrap();
+
+    let (instance, month) = parse_args(inv)?;
+
+    if let Some(s_name) = instance {
+        if !known.contains_key(s_name) {
+            return Err(usage_error!("Invalid instance."));
+        }
+    }
+
+    let servers = known
+        .iter()
+        .filter(|(s_name, _)| instance.map_or(true, |i| i == s_name.as_str()))
+        .collect::<Vec<_>>();
+
+    for (s_name, server_conf) in servers.iter() {
+        sync_runs(client, sql, locks, s_name, server_conf).await?;
+    }
+
+    let from = month.and_hms_opt(0, 0, 0).unwrap();
+    let to = next_month(month).and_hms_opt(0, 0, 0).unwrap();
+    let now = Utc::now().naive_utc();
+
+    let runs = runs_between(&mut sql.connection.get()?, from, to)?;
+
+    // Hours per instance and user, sorted by name
+    let mut hours: BTreeMap<&str, BTreeMap<Option<i64>, f64>> = BTreeMap::new();
+    for run in runs.iter() {
+        if instance.map_or(false, |i| i != run.server) {
+            continue;
+        }
+
+        let per_user = hours.entry(run.server.as_str()).or_default();
+        *per_user.entry(run.started_by).or_default() += run_hours(run, from, to, now);
+    }
+
+    if hours.is_empty() {
+        inv.respond(ctx, format!("No servers ran in {}.", month.format("%B %Y")))
+            .await?;
+        return Ok(());
+    }
+
+    let mut lines = vec![format!("Usage in {}:", month.format("%B %Y"))];
+    for (s_name, per_user) in hours {
+        lines.push(format!(
+            "**{}**: {:.1} h",
+            s_name,
+            per_user.values().sum::<f64>()
+        ));
+
+        for (user, h) in per_user {
+            let name = match user {
+                Some(id) => match UserId(id as u64).to_user(ctx).await {
+                    Ok(user) => user.tag(),
+                    Err(_) => id.to_string(),
+                },
+                None => "Scheduled or outside the bot".to_owned(),
+            };
+            lines.push(format!("- {}: {:.1} h", name, h));
+        }
+    }
+
+    inv.respond(ctx, lines.join("\n")).await?;
+
+    Ok(())
+}
+
+/// Opens or closes runs of servers started or stopped outside the bot.
+///
+/// Servers locked by a running task are skipped, as their runs are recorded by the task itself.
+pub async fn sync_runs(
+    client: &AzureClient,
+    sql: &Sql,
+    locks: &InstanceLocks,
+    s_name: &str,
+    server_conf: &ServerConfig,
+) -> SimpleResult<()> {
+    let lock = locks.get(s_name).await;
+    let _l = match lock.try_lock() {
+        Ok(l) => l,
+        Err(_) => return Ok(()),
+    };
+
+    let status = provider(client, server_conf).status().await?;
+    let changed_at = status.changed_at.unwrap_or_else(Utc::now).naive_utc();
+
+    let mut sql = sql.connection.get()?;
+    let open = is_open(&mut sql, s_name)?;
+
+    match status.power_state {
+        PowerState::Stopped | PowerState::Deallocated if open => {
+            info!(
+                "{} has been stopped outside the bot, closing its run.",
+                s_name
+            );
+            close(&mut sql, s_name, None, changed_at)?;
+        }
+        PowerState::Starting | PowerState::Running if !open => {
+            info!(
+                "{} has been started outside the bot, opening a run.",
+                s_name
+            );
+            let run = NewVmRun {
+                id: Uuid::random(),
+                server: s_name,
+                started_by: None,
+                started_at: changed_at,
+            };
+            run.insert(&mut sql)?;
+        }
+        _ => {}
+    }
+
+    Ok(())
+}
+
+/// Hours of the run within the period, open runs count until now.
+pub fn run_hours(run: &VmRun, from: NaiveDateTime, to: NaiveDateTime, now: NaiveDateTime) -> f64 {
+    let start = run.started_at.max(from);
+    let end = run.stopped_at.unwrap_or(now).min(to);
+
+    if end <= start {
+        return 0.0;
+    }
+
+    (end - start).num_seconds() as f64 / 3600.0
+}
+
+/// First day of the month after the given one.
+pub fn next_month(month: NaiveDate) -> NaiveDate {
+    (month.with_day(28).unwrap() + Duration::days(4))
+        .with_day(1)
+        .unwrap()
+}
+
+/// First day of the current month in UTC.
+pub fn current_month() -> NaiveDate {
+    Utc::now().naive_utc().date().with_day(1).unwrap()
+}
+
+/// Optional instance and month in any order, the month defaults to the current one.
+fn parse_args<'a>(inv: &'a Invocation<'_>) -> SimpleResult<(Option<&'a str>, NaiveDate)> {
+    let mut instance = None;
+    let mut month = None;
+
+    for arg in inv.args.iter().filter(|a| *a != OMITTED_ARG) {
+        match NaiveDate::parse_from_str(&format!("{}-01", arg), "%Y-%m-%d") {
+            Ok(m) if month.is_none() => month = Some(m),
+            Err(_) if instance.is_none() => instance = Some(arg.as_str()),
+            _ => return Err(syntax_error()),
+        }
+    }
+
+    Ok((instance, month.unwrap_or_else(current_month)))
+}
+
+fn syntax_error() -> SimpleError {
+    usage_error!("Syntax: {}usage [instance] [YYYY-MM].", CMD_PREFIX)
+}
+
+pub enum UsagePermission {
+    All,
+    Instance(String),
+}
+
+impl UsagePermission {
+    pub fn from_invocation(inv: &Invocation<'_>) -> SimpleResult<Self> {
+        match parse_args(inv)? {
+            (Some(s_name), _) => Ok(UsagePermission::Instance(s_name.to_owned())),
+            (None, _) => Ok(UsagePermission::All),
+        }
+    }
+}
+
+impl RbacPermission for UsagePermission {
+    type T = String;
+
+    fn rbac(&self) -> String {
+        match self {
+            UsagePermission::All => "/usage".to_owned(),
+            UsagePermission::Instance(s_name) => format!("/{}/usage", s_name),
+        }
+    }
+}
+
+has_permission! { UsagePermission }
@@ -1,18 +1,69 @@
-use crate::azure::{AzureId, AzureName, ClientId, Directory};
+use crate::azure::{AzureEnvironment, AzureId, AzureName, ClientId, Directory, Endpoints};
+use crate::command::usage_error;
+use crate::permission::rbac::Role;
 use crate::SimpleResult;
 use bimap::BiMap;
+use chrono_tz::Tz;
 use config::{Config, File, FileFormat};
+use cron::Schedule;
 use lazy_static::lazy_static;
+use log::warn;
+use regex::Regex;
+use serde::de::Error;
 use serde::{Deserialize, Deserializer};
-use serenity::model::id::GuildId;
+use serenity::model::id::{GuildId, UserId};
 use serenity::prelude::TypeMapKey;
 use std::borrow::Cow;
 use std::collections::HashMap;
 use std::env;
 use std::path::{Path, PathBuf};
+use std::str::FromStr;
+use std::sync::{Arc, RwLock};
 
 pub type Servers = HashMap<String, ServerConfig>;
 
+/// Servers configured by hand and the discovered ones.
+///
+/// The discovery swaps the servers while commands hold the data lock, commands keep using their snapshot.
+#[derive(Debug, Clone)]
+pub struct ServerMap {
+    configured: Arc<Servers>,
+    current: Arc<RwLock<Arc<Servers>>>,
+}
+
+impl ServerMap {
+    pub fn new(configured: Servers) -> Self {
+        let current = Arc::new(RwLock::new(Arc::new(configured.clone())));
+
+        ServerMap {
+            configured: Arc::new(configured),
+            current,
+        }
+    }
+
+    /// Servers from the config file, which take precedence over discovered ones.
+    pub fn configured(&self) -> &Servers {
+        &self.configured
+    }
+
+    pub fn snapshot(&self) -> Arc<Servers> {
+        Arc::clone(&self.current.read().unwrap())
+    }
+
+    pub fn replace(&self, servers: Servers) {
+        *self.current.write().unwrap() = Arc::new(servers);
+    }
+}
+
+impl<'de> Deserialize<'de> for ServerMap {
+    fn deserialize<D>(d: D) -> Result<Self, D::Error>
+    where
+        D: Deserializer<'de>,
+    {
+        Servers::deserialize(d).map(ServerMap::new)
+    }
+}
+
 fn prepare_path<'a>(path: impl Into<Cow<'a, Path>>) -> PathBuf {
     let path = path.into();
 
@@ -40,13 +91,234 @@ pub struct VmConfig {
     pub sub: AzureId,
 }
 
+/// Server managed by shell commands on the bot host, e.g. a box in the home network.
+#[derive(Debug, Clone, Deserialize)]
+pub struct LocalConfig {
+    pub power_on: String,
+    pub power_off: String,
+    /// Exits successfully while the server is powered on.
+    pub status: String,
+    /// Exits successfully once scripts can be run, otherwise the server is ready once powered on.
+    #[serde(default)]
+    pub ready: Option<String>,
+    /// Runs the scripts passed on stdin, `sh -s` by default.
+    #[serde(default)]
+    pub script_runner: Option<String>,
+}
+
+/// Compute backing the server, selected by the `vm` or `local` key.
+#[derive(Debug, Clone, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum ProviderConfig {
+    Vm(VmConfig),
+    Local(LocalConfig),
+}
+
 #[derive(Debug, Clone, Deserialize)]
 pub struct ServerConfig {
-    pub vm: VmConfig,
+    #[serde(flatten)]
+    pub provider: ProviderConfig,
     #[serde(deserialize_with = "deserialize_path_opt")]
     pub start_script: Option<PathBuf>,
     #[serde(deserialize_with = "deserialize_path_opt")]
     pub stop_script: Option<PathBuf>,
+    /// Script printing `idle` if nobody is using the server.
+    #[serde(default, deserialize_with = "deserialize_path_opt")]
+    pub idle_script: Option<PathBuf>,
+    /// Minutes the server has to be idle before it is stopped automatically.
+    #[serde(default)]
+    pub idle_timeout: Option<u64>,
+    /// Channel for notices about actions not triggered by a command.
+    #[serde(default)]
+    pub announce_channel: Option<u64>,
+    #[serde(default)]
+    pub schedule: Option<ScheduleConfig>,
+    #[serde(default)]
+    pub budget: Option<BudgetConfig>,
+    /// Maintenance scripts, which can be executed by `~exec <instance> <action>`.
+    #[serde(default)]
+    pub scripts: HashMap<String, ScriptConfig>,
+    #[serde(default)]
+    pub backup: Option<BackupConfig>,
+    /// Servers started before and stopped after this one.
+    #[serde(default)]
+    pub depends_on: Vec<String>,
+    /// Vm sizes `~resize` may change the server to.
+    #[serde(default)]
+    pub sizes: Vec<String>,
+    /// Connection address posted after a start, `{ip}` and `{fqdn}` are replaced.
+    #[serde(default)]
+    pub address: Option<String>,
+}
+
+impl ServerConfig {
+    /// The azure vm of the server, for commands only available for vms.
+    pub fn vm(&self) -> SimpleResult<&VmConfig> {
+        match self.provider {
+            ProviderConfig::Vm(ref vm) => Ok(vm),
+            ProviderConfig::Local(_) => Err(usage_error!("Only available for azure vms.")),
+        }
+    }
+
+    /// Server discovered from the tags of its vm.
+    pub fn from_tags(vm: VmConfig, tags: &HashMap<String, String>) -> Self {
+        let path = |tag: &str| tags.get(tag).map(|p| prepare_path(Path::new(p)));
+
+        let idle_timeout = tags
+            .get(TAG_IDLE_TIMEOUT)
+            .and_then(|timeout| match timeout.parse() {
+                Ok(timeout) => Some(timeout),
+                Err(why) => {
+                    warn!(
+                        "Ignoring invalid idle timeout tag {} of vm {}: {}",
+                        timeout, vm.name, why
+                    );
+                    None
+                }
+            });
+
+        ServerConfig {
+            provider: ProviderConfig::Vm(vm),
+            start_script: path(TAG_START_SCRIPT),
+            stop_script: path(TAG_STOP_SCRIPT),
+            idle_script: path(TAG_IDLE_SCRIPT),
+            idle_timeout,
+            announce_channel: None,
+            schedule: None,
+            budget: None,
+            scripts: HashMap::new(),
+            backup: None,
+            depends_on: tags
+                .get(TAG_DEPENDS_ON)
+                .map(|deps| deps.split(',').map(|d| d.trim().to_owned()).collect())
+                .unwrap_or_default(),
+            sizes: Vec::new(),
+            address: tags.get(TAG_ADDRESS).cloned(),
+        }
+    }
+}
+
+// Tags of discovered vms, the server tag holds the name of the server
+pub const TAG_SERVER: &str = "r6v3-server";
+const TAG_START_SCRIPT: &str = "r6v3-start-script";
+const TAG_STOP_SCRIPT: &str = "r6v3-stop-script";
+const TAG_IDLE_SCRIPT: &str = "r6v3-idle-script";
+const TAG_IDLE_TIMEOUT: &str = "r6v3-idle-timeout";
+const TAG_ADDRESS: &str = "r6v3-address";
+const TAG_DEPENDS_ON: &str = "r6v3-depends-on";
+
+#[derive(Debug, Clone, Deserialize)]
+pub struct DiscoveryConfig {
+    pub scopes: Vec<DiscoveryScope>,
+    /// Minutes between two refreshes.
+    #[serde(default = "default_discovery_interval")]
+    pub interval: u64,
+}
+
+fn default_discovery_interval() -> u64 {
+    10
+}
+
+/// Subscription, or one of its resource groups, searched for tagged vms.
+#[derive(Debug, Clone, Deserialize)]
+pub struct DiscoveryScope {
+    pub sub: AzureId,
+    #[serde(default)]
+    pub rg: Option<AzureName>,
+}
+
+#[derive(Debug, Clone, Deserialize)]
+pub struct BudgetConfig {
+    /// Hours the server may run per month.
+    pub hours: f64,
+    #[serde(default)]
+    pub cost_per_hour: Option<f64>,
+}
+
+#[derive(Debug, Clone, Deserialize)]
+pub struct BackupConfig {
+    /// Number of backups kept, older ones are deleted by `~backup`.
+    pub retention: usize,
+}
+
+#[derive(Debug, Clone, Deserialize)]
+pub struct ScriptConfig {
+    #[serde(deserialize_with = "deserialize_path")]
+    pub path: PathBuf,
+    #[serde(default)]
+    pub description: Option<String>,
+    /// Positional arguments of `~exec`, passed to the script as environment variables.
+    #[serde(default)]
+    pub parameters: Vec<ScriptParameter>,
+}
+
+#[derive(Debug, Clone, Deserialize)]
+pub struct ScriptParameter {
+    pub name: String,
+    #[serde(flatten)]
+    pub kind: ParameterKind,
+}
+
+#[derive(Debug, Clone, Deserialize)]
+#[serde(tag = "type", rename_all = "lowercase")]
+pub enum ParameterKind {
+    Enum {
+        values: Vec<String>,
+    },
+    Integer {
+        #[serde(default)]
+        min: Option<i64>,
+        #[serde(default)]
+        max: Option<i64>,
+    },
+    Regex {
+        #[serde(deserialize_with = "deserialize_regex")]
+        pattern: Regex,
+    },
+}
+
+/// The pattern has to match the whole value.
+fn deserialize_regex<'de, D>(d: D) -> Result<Regex, D::Error>
+where
+    D: Deserializer<'de>,
+{
+    let pattern = String::deserialize(d)?;
+    Regex::new(&format!("^(?:{})$", pattern)).map_err(D::Error::custom)
+}
+
+#[derive(Debug, Clone, Deserialize)]
+pub struct ScheduleConfig {
+    #[serde(default, deserialize_with = "deserialize_cron_opt")]
+    pub start: Option<Schedule>,
+    #[serde(default, deserialize_with = "deserialize_cron_opt")]
+    pub stop: Option<Schedule>,
+    #[serde(
+        default = "default_timezone",
+        deserialize_with = "deserialize_timezone"
+    )]
+    pub timezone: Tz,
+}
+
+fn default_timezone() -> Tz {
+    Tz::UTC
+}
+
+fn deserialize_cron_opt<'de, D>(d: D) -> Result<Option<Schedule>, D::Error>
+where
+    D: Deserializer<'de>,
+{
+    let expr: Option<String> = Deserialize::deserialize(d)?;
+    expr.map(|e| Schedule::from_str(&e).map_err(D::Error::custom))
+        .transpose()
+}
+
+fn deserialize_timezone<'de, D>(d: D) -> Result<Tz, D::Error>
+where
+    D: Deserializer<'de>,
+{
+    let tz = String::deserialize(d)?;
+    tz.parse()
+        .map_err(|_| D::Error::custom(format!("unknown timezone {}", tz)))
 }
 
 fn deserialize_path_opt<'de, D>(d: D) -> Result<Option<PathBuf>, D::Error>
@@ -68,12 +340,110 @@ where
 
 #[derive(Debug, Clone, Deserialize)]
 pub struct AzureClientConfig {
-    pub directory: Directory,
-    pub client: ClientId,
-    #[serde(deserialize_with = "deserialize_path")]
-    pub cert_path: PathBuf,
-    #[serde(deserialize_with = "deserialize_path")]
-    pub cert_key: PathBuf,
+    /// Tenant of the app registration, only optional for managed identities.
+    #[serde(default)]
+    pub directory: Option<Directory>,
+    #[serde(default)]
+    pub client: Option<ClientId>,
+    #[serde(default, deserialize_with = "deserialize_path_opt")]
+    pub cert_path: Option<PathBuf>,
+    #[serde(default, deserialize_with = "deserialize_path_opt")]
+    pub cert_key: Option<PathBuf>,
+    /// Credentials tried in order, defaults to the certificate.
+    #[serde(default)]
+    pub credentials: Vec<CredentialConfig>,
+    /// Seconds before the expiry of a token, from which on it is refreshed.
+    #[serde(default)]
+    pub token_refresh_skew: Option<u64>,
+    /// Seconds after which a token refresh is given up.
+    #[serde(default)]
+    pub token_refresh_timeout: Option<u64>,
+    /// Cloud providing the endpoints, defaults to the public azure cloud.
+    #[serde(default)]
+    pub environment: Option<AzureEnvironment>,
+    /// Base url of the token endpoint, overrides the one of the environment.
+    #[serde(default)]
+    pub authority: Option<String>,
+    /// Base url of the resource manager, overrides the one of the environment.
+    #[serde(default)]
+    pub resource_manager: Option<String>,
+    /// Token scope for the resource manager, derived from its url if only that is overridden.
+    #[serde(default)]
+    pub management_scope: Option<String>,
+}
+
+impl AzureClientConfig {
+    pub fn credentials(&self) -> Vec<CredentialConfig> {
+        match (&self.cert_path, &self.cert_key) {
+            (Some(cert_path), Some(cert_key)) if self.credentials.is_empty() => {
+                vec![CredentialConfig::Certificate {
+                    cert_path: cert_path.clone(),
+                    cert_key: cert_key.clone(),
+                }]
+            }
+            _ => self.credentials.clone(),
+        }
+    }
+
+    pub fn endpoints(&self) -> Endpoints {
+        let preset = self
+            .environment
+            .unwrap_or(AzureEnvironment::Public)
+            .endpoints();
+        let base =
+            |url: &Option<String>| url.as_deref().map(|u| u.trim_end_matches('/').to_owned());
+
+        let resource_manager = base(&self.resource_manager);
+        let management_scope = match (&self.management_scope, &resource_manager) {
+            (Some(scope), _) => scope.clone(),
+            (None, Some(resource_manager)) => format!("{}/.default", resource_manager),
+            (None, None) => preset.management_scope,
+        };
+
+        Endpoints {
+            authority: base(&self.authority).unwrap_or(preset.authority),
+            resource_manager: resource_manager.unwrap_or(preset.resource_manager),
+            management_scope,
+        }
+    }
+}
+
+#[derive(Debug, Clone, Deserialize)]
+#[serde(tag = "type", rename_all = "snake_case")]
+pub enum CredentialConfig {
+    Certificate {
+        #[serde(deserialize_with = "deserialize_path")]
+        cert_path: PathBuf,
+        #[serde(deserialize_with = "deserialize_path")]
+        cert_key: PathBuf,
+    },
+    ClientSecret {
+        secret: String,
+    },
+    ManagedIdentity {
+        /// Token endpoint of the instance metadata service.
+        #[serde(default)]
+        endpoint: Option<String>,
+        /// Client id of a user assigned identity.
+        #[serde(default)]
+        client: Option<ClientId>,
+    },
+    FederatedToken {
+        /// Defaults to `AZURE_FEDERATED_TOKEN_FILE`, as set by the azure workload identity webhook.
+        #[serde(default, deserialize_with = "deserialize_path_opt")]
+        token_file: Option<PathBuf>,
+    },
+}
+
+impl CredentialConfig {
+    pub fn name(&self) -> &'static str {
+        match self {
+            CredentialConfig::Certificate { .. } => "certificate",
+            CredentialConfig::ClientSecret { .. } => "client secret",
+            CredentialConfig::ManagedIdentity { .. } => "managed identity",
+            CredentialConfig::FederatedToken { .. } => "federated token",
+        }
+    }
 }
 
 #[derive(Debug, Clone, Deserialize)]
@@ -86,9 +456,32 @@ pub struct MovieTimeConf {
 pub struct Settings {
     pub discord_token: String,
     pub azure: AzureClientConfig,
-    pub servers: Servers,
+    pub servers: ServerMap,
     pub guilds: BiMap<String, GuildId>,
     pub movie_time: HashMap<String, MovieTimeConf>,
+    #[serde(default)]
+    pub quotas: QuotaConfig,
+    /// Channel getting an embed per privileged command
+    pub audit_channel: Option<u64>,
+    #[serde(default)]
+    pub discovery: Option<DiscoveryConfig>,
+}
+
+/// Number of servers a user may have running at once from their own starts.
+#[derive(Debug, Clone, Default, Deserialize)]
+pub struct QuotaConfig {
+    /// Takes precedence over the roles of the user
+    #[serde(default)]
+    pub users: HashMap<UserId, usize>,
+    /// The highest limit of all roles the user has applies
+    #[serde(default)]
+    pub roles: HashMap<Role, usize>,
+}
+
+impl QuotaConfig {
+    pub fn is_empty(&self) -> bool {
+        self.users.is_empty() && self.roles.is_empty()
+    }
 }
 
 impl Settings {
@@ -0,0 +1,243 @@
+use crate::azure::management::vm::PowerState;
+use crate::azure::AzureClient;
+use crate::command::start::start_server;
+use crate::command::stop::stop_server;
+use crate::command::{
+    progress, usage_error, CancelKey, CancelToken, InstanceLockKey, ProgressMessage,
+};
+use crate::conf::{ServerConfig, Servers};
+use crate::job::{Job, JobKind};
+use crate::provider::provider;
+use crate::sql::SqlKey;
+use crate::{AzureClientKey, ConfigKey, SimpleResult};
+use log::info;
+use serenity::client::Context;
+use serenity::model::id::UserId;
+use serenity::prelude::TypeMap;
+use std::collections::HashSet;
+
+/// Transitive dependencies of the server in start order, without the server itself.
+pub fn start_order(servers: &Servers, s_name: &str) -> SimpleResult<Vec<String>> {
+    fn visit(
+        servers: &Servers,
+        s_name: &str,
+        path: &mut Vec<String>,
+        order: &mut Vec<String>,
+    ) -> SimpleResult<()> {
+        if let Some(pos) = path.iter().position(|s| s == s_name) {
+            let mut cycle = path[pos..].to_vec();
+            cycle.push(s_name.to_owned());
+            return Err(usage_error!("Dependency cycle: {}", cycle.join(" -> ")));
+        }
+
+        if order.iter().any(|s| s == s_name) {
+            return Ok(());
+        }
+
+        let server_conf = servers
+            .get(s_name)
+            .ok_or_else(|| usage_error!("Unknown dependency {}.", s_name))?;
+
+        path.push(s_name.to_owned());
+        for dep in server_conf.depends_on.iter() {
+            visit(servers, dep, path, order)?;
+        }
+        path.pop();
+
+        order.push(s_name.to_owned());
+        Ok(())
+    }
+
+    let mut order = Vec::new();
+    visit(servers, s_name, &mut Vec::new(), &mut order)?;
+    order.pop();
+
+    Ok(order)
+}
+
+/// Servers depending on the given one, directly or transitively.
+pub fn dependents<'a>(servers: &'a Servers, s_name: &str) -> Vec<&'a str> {
+    servers
+        .keys()
+        .filter(|other| {
+            start_order(servers, other).map_or(false, |deps| deps.iter().any(|d| d == s_name))
+        })
+        .map(String::as_str)
+        .collect()
+}
+
+/// Starts the dependencies of the server, which aren't running yet, one after another.
+///
+/// Cancelling the token of the server aborts the whole chain.
+pub async fn start_dependencies(
+    ctx: &Context,
+    data: &TypeMap,
+    s_name: &str,
+    progress: &mut ProgressMessage<'_>,
+    cancel: &CancelToken,
+    user: Option<UserId>,
+) -> SimpleResult<()> {
+    let servers = data.get::<ConfigKey>().unwrap().servers.snapshot();
+    let client = data.get::<AzureClientKey>().unwrap();
+
+    for dep in start_order(&servers, s_name)? {
+        let dep_conf = &servers[&dep];
+
+        let _l = lock(data, &dep).await?;
+
+        if is_up(client, dep_conf).await? {
+            continue;
+        }
+
+        progress!(progress, ctx, format!("Starting dependency {} ...", dep));
+        info!("Starting {} as dependency of {}.", dep, s_name);
+
+        // The cancel button of the progress message refers to the dependency meanwhile
+        let cancel = data
+            .get::<CancelKey>()
+            .unwrap()
+            .register_token(&dep, cancel.clone());
+        let job = Job::begin(
+            data.get::<SqlKey>().unwrap(),
+            &dep,
+            JobKind::Start,
+            None,
+            progress.channel(),
+            user,
+        )?;
+
+        start_server(ctx, client, &dep, dep_conf, progress, &cancel, &job).await?;
+    }
+
+    Ok(())
+}
+
+/// Running servers which depend on the server.
+pub async fn running_dependents<'a>(
+    client: &AzureClient,
+    servers: &'a Servers,
+    s_name: &str,
+) -> SimpleResult<Vec<&'a str>> {
+    let mut running = Vec::new();
+    for other in dependents(servers, s_name) {
+        if is_up(client, &servers[other]).await? {
+            running.push(other);
+        }
+    }
+
+    Ok(running)
+}
+
+/// Stops the server and afterwards its dependencies, the caller must hold the instance lock of the server.
+pub async fn stop_with_dependencies(
+    ctx: &Context,
+    data: &TypeMap,
+    s_name: &str,
+    server_conf: &ServerConfig,
+    progress: &mut ProgressMessage<'_>,
+    user: Option<UserId>,
+) -> SimpleResult<()> {
+    let client = data.get::<AzureClientKey>().unwrap();
+
+    let job = Job::begin(
+        data.get::<SqlKey>().unwrap(),
+        s_name,
+        JobKind::Stop,
+        None,
+        progress.channel(),
+        user,
+    )?;
+    stop_server(ctx, client, s_name, server_conf, progress, &job).await?;
+
+    stop_dependencies(ctx, data, s_name, progress, user).await
+}
+
+/// Stops the dependencies of the stopped server in reverse start order.
+///
+/// Dependencies still needed by another running server are kept, the result is summarized in the progress message.
+pub async fn stop_dependencies(
+    ctx: &Context,
+    data: &TypeMap,
+    s_name: &str,
+    progress: &mut ProgressMessage<'_>,
+    user: Option<UserId>,
+) -> SimpleResult<()> {
+    let servers = data.get::<ConfigKey>().unwrap().servers.snapshot();
+    let client = data.get::<AzureClientKey>().unwrap();
+
+    let deps = start_order(&servers, s_name)?;
+    if deps.is_empty() {
+        return Ok(());
+    }
+
+    let mut stopped = HashSet::from([s_name.to_owned()]);
+    let mut summary = Vec::new();
+
+    for dep in deps.into_iter().rev() {
+        let dep_conf = &servers[&dep];
+
+        // Starts of dependents check this dependency under the same lock
+        let _l = lock(data, &dep).await?;
+
+        let mut needed_by = running_dependents(client, &servers, &dep).await?;
+        needed_by.retain(|other| !stopped.contains(*other));
+
+        if !needed_by.is_empty() {
+            info!("Keeping {}, it is needed by {}.", dep, needed_by.join(", "));
+            summary.push(format!("{} kept for {}", dep, needed_by.join(", ")));
+            continue;
+        }
+
+        if is_up(client, dep_conf).await? {
+            progress!(progress, ctx, format!("Stopping dependency {} ...", dep));
+            info!("Stopping {} as dependency of {}.", dep, s_name);
+
+            let job = Job::begin(
+                data.get::<SqlKey>().unwrap(),
+                &dep,
+                JobKind::Stop,
+                None,
+                progress.channel(),
+                user,
+            )?;
+
+            stop_server(ctx, client, &dep, dep_conf, progress, &job).await?;
+            summary.push(format!("{} stopped", dep));
+        }
+
+        stopped.insert(dep);
+    }
+
+    if !summary.is_empty() {
+        progress!(
+            progress,
+            ctx,
+            format!("Stopped the server. Dependencies: {}.", summary.join(", "))
+        );
+    }
+
+    Ok(())
+}
+
+async fn lock(data: &TypeMap, s_name: &str) -> SimpleResult<tokio::sync::OwnedMutexGuard<()>> {
+    data.get::<InstanceLockKey>()
+        .unwrap()
+        .get(s_name)
+        .await
+        .try_lock_owned()
+        .map_err(|_| {
+            usage_error!(
+                "Dependency {} is blocked by another task, try again later.",
+                s_name
+            )
+        })
+}
+
+async fn is_up(client: &AzureClient, server_conf: &ServerConfig) -> SimpleResult<bool> {
+    let power_state = provider(client, server_conf).status().await?.power_state;
+
+    Ok(matches!(
+        power_state,
+        PowerState::Starting | PowerState::Running
+    ))
+}
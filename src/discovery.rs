@@ -0,0 +1,106 @@
+use crate::azure::management::vm::VmClient;
+use crate::azure::AzureClient;
+use crate::conf::{
+    ConfigKey, DiscoveryConfig, ServerConfig, ServerMap, Servers, VmConfig, TAG_SERVER,
+};
+use crate::{AzureClientKey, SimpleResult};
+use log::{error, info, warn};
+use serenity::client::Context;
+use std::sync::Arc;
+use std::time::Duration;
+use tokio::time::sleep;
+
+/// Refreshes the discovered servers periodically, after the first refresh.
+pub fn spawn_discovery(ctx: Arc<Context>, interval: Duration) {
+    info!("Spawning discovery.");
+
+    tokio::spawn(async move { discovery(ctx, interval).await });
+}
+
+async fn discovery(ctx: Arc<Context>, mut interval: Duration) {
+    loop {
+        sleep(interval).await;
+
+        interval = match discover(&ctx).await {
+            Some(interval) => interval,
+            None => return,
+        };
+    }
+}
+
+/// Refreshes the discovered servers, returns the time until the next refresh or None if discovery is disabled.
+pub async fn discover(ctx: &Context) -> Option<Duration> {
+    let data = ctx.data.read().await;
+    let config = data.get::<ConfigKey>().unwrap();
+    let client = data.get::<AzureClientKey>().unwrap();
+
+    let discovery_conf = config.discovery.as_ref()?;
+    let interval = Duration::from_secs(discovery_conf.interval * 60);
+
+    match collect(client, discovery_conf).await {
+        Ok(found) => merge(&config.servers, found),
+        Err(why) => error!("Error discovering servers: {}", why),
+    }
+
+    Some(interval)
+}
+
+async fn collect(client: &AzureClient, discovery_conf: &DiscoveryConfig) -> SimpleResult<Servers> {
+    let mut found = Servers::new();
+
+    for scope in discovery_conf.scopes.iter() {
+        for vm in client.list(&scope.sub, scope.rg.as_ref()).await? {
+            let (s_name, rg) = match (vm.tags.get(TAG_SERVER), vm.resource_group()) {
+                (Some(s_name), Some(rg)) => (s_name.clone(), rg.to_owned()),
+                _ => continue,
+            };
+
+            let vm_conf = VmConfig {
+                name: vm.name.clone().into(),
+                rg: rg.into(),
+                sub: scope.sub.clone(),
+            };
+
+            if found.contains_key(&s_name) {
+                warn!(
+                    "Discovered server {} more than once, ignoring {}.",
+                    s_name, vm.id
+                );
+                continue;
+            }
+
+            found.insert(s_name, ServerConfig::from_tags(vm_conf, &vm.tags));
+        }
+    }
+
+    Ok(found)
+}
+
+/// Replaces the previously discovered servers, servers configured by hand take precedence.
+fn merge(servers: &ServerMap, found: Servers) {
+    let previous = servers.snapshot();
+    let mut merged = servers.configured().clone();
+
+    for (s_name, server_conf) in found {
+        if merged.contains_key(&s_name) {
+            warn!(
+                "Discovered server {} is already configured by hand, ignoring it.",
+                s_name
+            );
+            continue;
+        }
+
+        if !previous.contains_key(&s_name) {
+            info!("Discovered server {}.", s_name);
+        }
+
+        merged.insert(s_name, server_conf);
+    }
+
+    for s_name in previous.keys().filter(|s| !merged.contains_key(*s)) {
+        info!("Server {} is no longer tagged, removing it.", s_name);
+    }
+
+    // Running commands keep their snapshot, the data lock isn't needed
+    servers.replace(merged);
+}
@@ -1,6 +1,12 @@
+use crate::budget::spawn_budget_worker;
 use crate::conf::ConfigKey;
+use crate::discovery::{discover, spawn_discovery};
+use crate::idle::spawn_idle_worker;
+use crate::interaction::{handle_interaction, register_commands};
+use crate::job::spawn_reconciler;
 use crate::movie::worker::{spawn_movie_worker, Message as WorkerMessage, WorkerChannel};
 use crate::movie::{handle_groupwatch_default_channel, MOVIE_URIS};
+use crate::schedule::spawn_scheduler;
 use crate::sql::movie::uuid_from_vc;
 use crate::sql::SqlKey;
 use crate::voice::vc_is_empty;
@@ -8,20 +14,42 @@ use async_trait::async_trait;
 use log::{debug, error, info};
 use serenity::client::{Context, EventHandler};
 use serenity::model::channel::Message;
-use serenity::model::gateway::Activity;
+use serenity::model::gateway::{Activity, Ready};
 use serenity::model::id::ChannelId;
 use serenity::model::id::GuildId;
+use serenity::model::interactions::Interaction;
 use serenity::model::voice::VoiceState;
 use std::sync::atomic::{AtomicBool, Ordering};
 use std::sync::Arc;
 
 #[derive(Default)]
 pub struct Handler {
-    movie_worker_spawned: AtomicBool,
+    workers_spawned: AtomicBool,
 }
 
 #[async_trait]
 impl EventHandler for Handler {
+    async fn ready(&self, ctx: Context, _ready: Ready) {
+        let guilds = {
+            let data = ctx.data.read().await;
+            let conf = data.get::<ConfigKey>().unwrap();
+            conf.guilds.right_values().copied().collect::<Vec<_>>()
+        };
+
+        for guild in guilds {
+            if let Err(why) = register_commands(&ctx, guild).await {
+                error!(
+                    "Error registering slash commands for guild {}: {}",
+                    guild, why
+                );
+            }
+        }
+    }
+
+    async fn interaction_create(&self, ctx: Context, interaction: Interaction) {
+        handle_interaction(&ctx, interaction).await;
+    }
+
     async fn cache_ready(&self, ctx: Context, _guilds: Vec<GuildId>) {
         info!("Bot up and running!");
 
@@ -29,7 +57,7 @@ impl EventHandler for Handler {
 
         ctx.set_activity(activity).await;
 
-        let worker_running = self.movie_worker_spawned.swap(true, Ordering::Relaxed);
+        let worker_running = self.workers_spawned.swap(true, Ordering::Relaxed);
 
         if worker_running {
             return;
@@ -43,6 +71,16 @@ impl EventHandler for Handler {
             let mut data = ctx.data.write().await;
             data.insert::<WorkerChannel>(tx);
         }
+
+        // The other workers need to know the discovered servers from the start
+        if let Some(interval) = discover(&ctx).await {
+            spawn_discovery(Arc::clone(&ctx), interval);
+        }
+
+        spawn_reconciler(Arc::clone(&ctx));
+        spawn_idle_worker(Arc::clone(&ctx));
+        spawn_scheduler(Arc::clone(&ctx));
+        spawn_budget_worker(Arc::clone(&ctx));
     }
 
     async fn message(&self, ctx: Context, msg: Message) {
@@ -1,7 +1,17 @@
+use crate::audit;
+use crate::command::backup::BackupPermission;
+use crate::command::exec::ExecPermission;
 use crate::command::ping::PingPermission;
+use crate::command::resize::ResizePermission;
+use crate::command::restart::RestartPermission;
+use crate::command::schedule::SchedulePermission;
 use crate::command::start::StartPermission;
+use crate::command::status::StatusPermission;
 use crate::command::stop::StopPermission;
-use crate::permission::HasPermission;
+use crate::command::usage::UsagePermission;
+use crate::command::{server_name, Invocation};
+use crate::owners::Owners;
+use crate::permission::check_permission;
 use crate::{SimpleError, SimpleResult};
 use log::{error, info, warn};
 use serenity::client::Context;
@@ -11,115 +21,180 @@ use serenity::model::channel::Message;
 
 #[hook]
 pub async fn before_hook(ctx: &Context, msg: &Message, cmd_name: &str) -> bool {
+    authorize(ctx, &Invocation::from_message(msg, cmd_name)).await
+}
+
+#[hook]
+pub async fn after_hook(
+    ctx: &Context,
+    msg: &Message,
+    cmd_name: &str,
+    res: Result<(), CommandError>,
+) {
+    let inv = Invocation::from_message(msg, cmd_name);
+
+    let res = match res {
+        Ok(()) => Ok(()),
+        Err(why) => match why.downcast::<SimpleError>() {
+            Ok(e) => Err(*e),
+            Err(why) => {
+                error!("Unknown Error: {}", why);
+                audit::record(ctx, &inv, true, Some(&why.to_string())).await;
+                return;
+            }
+        },
+    };
+
+    finish(ctx, &inv, res).await;
+}
+
+/// Checks the permission for the invocation and notifies the user, if the check fails.
+pub async fn authorize(ctx: &Context, inv: &Invocation<'_>) -> bool {
     info!(
         "Received command {} from user {}#{} ({}).",
-        cmd_name, msg.author.name, msg.author.discriminator, msg.author.id
+        inv.name, inv.user.name, inv.user.discriminator, inv.user.id
     );
 
-    let perm_check = has_permission(ctx, msg, cmd_name).await;
+    let perm_check = has_permission(ctx, inv).await;
 
     match perm_check {
         Ok(false) => {
             info!(
                 "Unauthorized command usage: {} from {}#{} ({}).",
-                cmd_name, msg.author.name, msg.author.discriminator, msg.author.id
+                inv.name, inv.user.name, inv.user.discriminator, inv.user.id
             );
 
-            let res = msg.reply(ctx, "Not authorized.").await;
+            let res = inv.respond(ctx, "Not authorized.").await;
             if let Err(why) = res {
                 warn!("An error occurred replying to the author.: {:?}", why);
             }
 
+            audit::record(ctx, inv, false, None).await;
             false
         }
 
         Ok(true) => true,
         Err(why) => {
-            handle_error(&why, ctx, msg).await;
+            handle_error(&why, ctx, inv).await;
+            audit::record(ctx, inv, false, Some(&why.to_string())).await;
             false
         }
     }
 }
 
-#[hook]
-pub async fn after_hook(
-    ctx: &Context,
-    msg: &Message,
-    cmd_name: &str,
-    res: Result<(), CommandError>,
-) {
-    if let Err(why) = res {
-        if let Some(e) = why.downcast_ref::<SimpleError>() {
-            handle_error(e, ctx, msg).await;
-        } else {
-            error!("Unknown Error: {}", why);
+/// Logs the result of an authorized invocation, reports errors to the user and records it in the audit log.
+pub async fn finish(ctx: &Context, inv: &Invocation<'_>, res: SimpleResult<()>) {
+    match res {
+        Ok(()) => {
+            log_success(inv);
+            audit::record(ctx, inv, true, None).await;
+        }
+        Err(why) => {
+            handle_error(&why, ctx, inv).await;
+            audit::record(ctx, inv, true, Some(&why.to_string())).await;
         }
-    } else {
-        info!(
-            "Successfully processed command {} from user {}#{} ({}).",
-            cmd_name, msg.author.name, msg.author.discriminator, msg.author.id
-        );
     }
 }
 
-async fn has_permission(ctx: &Context, msg: &Message, cmd_name: &str) -> SimpleResult<bool> {
-    let user = msg.author.id;
-
-    macro_rules! check_roles {
-        ($perm:expr) => {{
-            match msg.guild(ctx) {
-                None => false,
-                Some(g) => match g.member(ctx, user).await {
-                    Ok(member) => {
-                        let mut check = false;
-                        for r in member.roles.iter() {
-                            if r.has_permission(ctx, $perm).await {
-                                check = true;
-                                break;
-                            }
-                        }
-                        check
-                    }
-                    Err(e) => return Err(e.into()),
-                },
-            }
-        }};
-    }
+fn log_success(inv: &Invocation<'_>) {
+    info!(
+        "Successfully processed command {} from user {}#{} ({}).",
+        inv.name, inv.user.name, inv.user.discriminator, inv.user.id
+    );
+}
+
+pub async fn has_permission(ctx: &Context, inv: &Invocation<'_>) -> SimpleResult<bool> {
+    let user = inv.user.id;
 
     macro_rules! check_permission {
-        ($perm:expr) => {{
-            let p = $perm;
-            user.has_permission(ctx, &p).await || check_roles!(&p)
-        }};
+        ($perm:expr) => {
+            check_permission(ctx, inv.guild_id, user, &$perm).await?
+        };
     }
 
-    let r = match cmd_name {
+    let r = match inv.name {
         "ping" => check_permission!(PingPermission),
-        "start" => check_permission!(StartPermission::from_message(msg)?),
-        "stop" => check_permission!(StopPermission::from_message(msg)?),
+        // Only lists the servers the user may start or stop
+        "list" => true,
+        "start" => check_permission!(StartPermission::from_invocation(inv)?),
+        "stop" => check_permission!(StopPermission::from_invocation(inv)?),
+        // Cancelling a start deallocates the server, just like stopping it
+        "cancel" => check_permission!(StopPermission::from_invocation(inv)?),
+        "restart" => check_permission!(RestartPermission::from_invocation(inv)?),
+        "status" => check_permission!(StatusPermission::from_invocation(inv)?),
+        "schedule" => check_permission!(SchedulePermission::from_invocation(inv)?),
+        "exec" => check_permission!(ExecPermission::from_invocation(inv)?),
+        "usage" => check_permission!(UsagePermission::from_invocation(inv)?),
+        "resize" => check_permission!(ResizePermission::from_invocation(inv)?),
+        "backup" | "backups" | "restore" => {
+            check_permission!(BackupPermission::from_invocation(inv)?)
+        }
+        "audit" => {
+            let data = ctx.data.read().await;
+            data.get::<Owners>().unwrap().contains(&user)
+        }
         _ => false,
     };
 
     Ok(r)
 }
 
-async fn handle_error(err: &SimpleError, ctx: &Context, msg: &Message) {
+/// Instance the command targets, resolved from its arguments like the permission.
+pub fn target_instance(inv: &Invocation<'_>) -> Option<String> {
+    match inv.name {
+        "start" | "stop" | "cancel" | "restart" | "status" | "resize" | "backup" | "backups"
+        | "restore" => server_name(inv).ok().map(str::to_owned),
+        "schedule" => match SchedulePermission::from_invocation(inv) {
+            Ok(SchedulePermission::Modify(s_name)) => Some(s_name),
+            _ => None,
+        },
+        "exec" => ExecPermission::from_invocation(inv).ok().map(|p| p.0),
+        "usage" => match UsagePermission::from_invocation(inv) {
+            Ok(UsagePermission::Instance(s_name)) => Some(s_name),
+            _ => None,
+        },
+        _ => None,
+    }
+}
+
+async fn handle_error(err: &SimpleError, ctx: &Context, inv: &Invocation<'_>) {
+    if let SimpleError::Cancelled = err {
+        info!("Command {} has been cancelled.", inv.name);
+        return;
+    }
+
     if let SimpleError::UsageError(ref why) = err {
         info!("Command usage error: {}", why);
-        if let Err(inner) = msg.reply(ctx, why).await.map_err(Into::into) {
-            print_error(&inner, ctx, msg).await;
+        if let Err(inner) = inv.respond(ctx, why).await {
+            print_error(&inner, ctx, inv).await;
+        }
+
+        return;
+    }
+
+    if let SimpleError::ScriptFailed(ref output) = err {
+        warn!(
+            "Script of command {} failed: {}",
+            inv.name,
+            output.summary()
+        );
+        let res = inv
+            .respond_with_output(ctx, format!("{}.", output.summary()), output)
+            .await;
+        if let Err(inner) = res {
+            print_error(&inner, ctx, inv).await;
         }
 
         return;
     }
 
     error!("Command execution unsuccessful: {:?}", err);
-    print_error(err, ctx, msg).await;
+    print_error(err, ctx, inv).await;
 }
 
-async fn print_error(err: &SimpleError, ctx: &Context, msg: &Message) {
-    let res = msg
-        .reply(ctx, format!("An internal error occurred: {}", err))
+async fn print_error(err: &SimpleError, ctx: &Context, inv: &Invocation<'_>) {
+    let res = inv
+        .respond(ctx, format!("An internal error occurred: {}", err))
         .await;
 
     if let Err(why) = res {
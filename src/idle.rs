@@ -0,0 +1,142 @@
+use crate::azure::management::vm::PowerState;
+use crate::command::{read_script, InstanceLockKey, ProgressMessage};
+use crate::conf::{ConfigKey, ServerConfig};
+use crate::dependency::{running_dependents, stop_with_dependencies};
+use crate::provider::provider;
+use crate::{AzureClientKey, SimpleResult};
+use log::{debug, info, warn};
+use serenity::client::Context;
+use serenity::model::id::ChannelId;
+use std::collections::HashMap;
+use std::sync::Arc;
+use std::time::{Duration, SystemTime};
+use tokio::time::sleep;
+
+const IDLE_CHECK_INTERVAL: Duration = Duration::from_secs(300);
+const IDLE_SCRIPT_TIMEOUT: Duration = Duration::from_secs(120);
+
+pub fn spawn_idle_worker(ctx: Arc<Context>) {
+    info!("Spawning idle worker.");
+
+    tokio::spawn(async move { idle_worker(ctx).await });
+}
+
+async fn idle_worker(ctx: Arc<Context>) {
+    let mut idle_since = HashMap::new();
+
+    loop {
+        sleep(IDLE_CHECK_INTERVAL).await;
+
+        let servers = {
+            let data = ctx.data.read().await;
+            let config = data.get::<ConfigKey>().unwrap();
+            config
+                .servers
+                .snapshot()
+                .iter()
+                .filter(|(_, c)| c.idle_script.is_some() && c.idle_timeout.is_some())
+                .map(|(s_name, c)| (s_name.clone(), c.clone()))
+                .collect::<Vec<_>>()
+        };
+
+        for (s_name, server_conf) in servers {
+            match is_idle(&ctx, &s_name, &server_conf).await {
+                Ok(true) => {
+                    let since = *idle_since
+                        .entry(s_name.clone())
+                        .or_insert_with(SystemTime::now);
+                    let timeout = Duration::from_secs(server_conf.idle_timeout.unwrap() * 60);
+
+                    if SystemTime::now().duration_since(since).unwrap_or_default() < timeout {
+                        debug!("Instance {} is idle, waiting for idle timeout.", s_name);
+                        continue;
+                    }
+
+                    match shutdown(&ctx, &s_name, &server_conf).await {
+                        Ok(true) => {
+                            idle_since.remove(&s_name);
+                        }
+                        Ok(false) => debug!(
+                            "Idle shutdown of {} blocked by another task, retrying later.",
+                            s_name
+                        ),
+                        Err(why) => warn!("Error stopping idle instance {}: {}", s_name, why),
+                    }
+                }
+                Ok(false) => {
+                    idle_since.remove(&s_name);
+                }
+                Err(why) => warn!("Error checking idle state of {}: {}", s_name, why),
+            }
+        }
+    }
+}
+
+async fn is_idle(ctx: &Context, s_name: &str, server_conf: &ServerConfig) -> SimpleResult<bool> {
+    let data = ctx.data.read().await;
+    let client = data.get::<AzureClientKey>().unwrap();
+    let locks = data.get::<InstanceLockKey>().unwrap();
+
+    // Instances currently handled by a command are never idle
+    if let Some(lock) = locks.try_get(s_name).await {
+        if lock.try_lock().is_err() {
+            return Ok(false);
+        }
+    }
+
+    // Servers needed by a running server are never idle
+    let servers = data.get::<ConfigKey>().unwrap().servers.snapshot();
+    if !running_dependents(client, &servers, s_name)
+        .await?
+        .is_empty()
+    {
+        return Ok(false);
+    }
+
+    let provider = provider(client, server_conf);
+    let status = provider.status().await?;
+
+    if status.power_state != PowerState::Running || !status.ready {
+        return Ok(false);
+    }
+
+    let script = read_script(server_conf.idle_script.as_deref(), "idle")?;
+
+    let output = provider
+        .run_script(&script, Vec::new(), IDLE_SCRIPT_TIMEOUT)
+        .await?
+        .into_result()?;
+
+    Ok(output.stdout.lines().any(|l| l.trim() == "idle"))
+}
+
+async fn shutdown(ctx: &Context, s_name: &str, server_conf: &ServerConfig) -> SimpleResult<bool> {
+    let data = ctx.data.read().await;
+    let locks = data.get::<InstanceLockKey>().unwrap();
+
+    let _l = match locks.get(s_name).await.try_lock_owned() {
+        Ok(l) => l,
+        Err(_) => return Ok(false),
+    };
+
+    let timeout = server_conf.idle_timeout.unwrap();
+    info!(
+        "Instance {} has been idle for {} minutes, stopping it.",
+        s_name, timeout
+    );
+
+    let mut progress = match server_conf.announce_channel {
+        Some(channel) => ProgressMessage::in_channel(
+            ChannelId(channel),
+            format!(
+                "**{}** has been idle for {} minutes, stopping it.",
+                s_name, timeout
+            ),
+        ),
+        None => ProgressMessage::silent(),
+    };
+
+    stop_with_dependencies(ctx, &data, s_name, server_conf, &mut progress, None).await?;
+
+    Ok(true)
+}
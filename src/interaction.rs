@@ -0,0 +1,452 @@
+use crate::command::{
+    audit, backup, exec, list, ping, resize, restart, schedule, start, status, stop, usage,
+    Invocation, ProgressButton, OMITTED_ARG,
+};
+use crate::conf::ConfigKey;
+use crate::hook::{authorize, finish, has_permission};
+use crate::SimpleResult;
+use log::{info, warn};
+use serde_json::Value;
+use serenity::client::Context;
+use serenity::model::id::GuildId;
+use serenity::model::interactions::application_command::{
+    ApplicationCommandInteraction, ApplicationCommandInteractionDataOption,
+    ApplicationCommandOptionType,
+};
+use serenity::model::interactions::autocomplete::AutocompleteInteraction;
+use serenity::model::interactions::message_component::MessageComponentInteraction;
+use serenity::model::interactions::{Interaction, InteractionResponseType};
+
+// Discord doesn't accept more than 25 autocomplete choices
+const MAX_CHOICES: usize = 25;
+
+struct SlashCommand {
+    name: &'static str,
+    description: &'static str,
+    options: &'static [SlashOption],
+}
+
+struct SlashOption {
+    name: &'static str,
+    description: &'static str,
+    required: bool,
+    kind: SlashOptionKind,
+}
+
+enum SlashOptionKind {
+    /// Autocompleted from the configured servers
+    Instance,
+    /// Autocompleted from the scripts of the server given in the instance option
+    Script,
+    /// Autocompleted from the allowed sizes of the server given in the instance option
+    Size,
+    /// Free text, split into multiple arguments at whitespace
+    Text,
+    Choice(&'static [&'static str]),
+}
+
+const INSTANCE: SlashOption = SlashOption {
+    name: "instance",
+    description: "Name of the server",
+    required: true,
+    kind: SlashOptionKind::Instance,
+};
+
+const COMMANDS: &[SlashCommand] = &[
+    SlashCommand {
+        name: "ping",
+        description: "Replies with Pong!",
+        options: &[],
+    },
+    SlashCommand {
+        name: "list",
+        description: "Lists the servers and their power state",
+        options: &[],
+    },
+    SlashCommand {
+        name: "start",
+        description: "Starts a server",
+        options: &[INSTANCE],
+    },
+    SlashCommand {
+        name: "stop",
+        description: "Stops a server",
+        options: &[INSTANCE],
+    },
+    SlashCommand {
+        name: "restart",
+        description: "Restarts a server",
+        options: &[INSTANCE],
+    },
+    SlashCommand {
+        name: "status",
+        description: "Shows the status of a server",
+        options: &[INSTANCE],
+    },
+    SlashCommand {
+        name: "schedule",
+        description: "Lists, pauses or resumes the start and stop schedules",
+        options: &[
+            SlashOption {
+                name: "action",
+                description: "What to do with the schedules",
+                required: true,
+                kind: SlashOptionKind::Choice(&["list", "pause", "resume"]),
+            },
+            SlashOption {
+                required: false,
+                ..INSTANCE
+            },
+        ],
+    },
+    SlashCommand {
+        name: "exec",
+        description: "Executes a maintenance script on a server",
+        options: &[
+            INSTANCE,
+            SlashOption {
+                name: "action",
+                description: "Name of the script",
+                required: true,
+                kind: SlashOptionKind::Script,
+            },
+            SlashOption {
+                name: "arguments",
+                description: "Parameters of the script, separated by spaces",
+                required: false,
+                kind: SlashOptionKind::Text,
+            },
+        ],
+    },
+    SlashCommand {
+        name: "usage",
+        description: "Shows the running hours per server and user",
+        options: &[
+            SlashOption {
+                required: false,
+                ..INSTANCE
+            },
+            SlashOption {
+                name: "month",
+                description: "Month in the format YYYY-MM, defaults to the current one",
+                required: false,
+                kind: SlashOptionKind::Text,
+            },
+        ],
+    },
+    SlashCommand {
+        name: "resize",
+        description: "Shows or changes the size of a stopped server",
+        options: &[
+            INSTANCE,
+            SlashOption {
+                name: "size",
+                description: "New vm size, shows the current and allowed sizes if omitted",
+                required: false,
+                kind: SlashOptionKind::Size,
+            },
+        ],
+    },
+    SlashCommand {
+        name: "backup",
+        description: "Snapshots the disks of a server",
+        options: &[INSTANCE],
+    },
+    SlashCommand {
+        name: "backups",
+        description: "Lists the backups of a server",
+        options: &[INSTANCE],
+    },
+    SlashCommand {
+        name: "restore",
+        description: "Replaces the disks of a stopped server with a backup",
+        options: &[
+            INSTANCE,
+            SlashOption {
+                name: "backup",
+                description: "Name of the backup, as listed by backups",
+                required: true,
+                kind: SlashOptionKind::Text,
+            },
+        ],
+    },
+    SlashCommand {
+        name: "audit",
+        description: "Shows the latest commands and their outcome",
+        options: &[
+            SlashOption {
+                name: "filter",
+                description: "Instance or user to show the commands of",
+                required: false,
+                kind: SlashOptionKind::Text,
+            },
+            SlashOption {
+                name: "limit",
+                description: "Number of commands to show",
+                required: false,
+                kind: SlashOptionKind::Text,
+            },
+        ],
+    },
+];
+
+async fn execute(ctx: &Context, inv: &Invocation<'_>) -> SimpleResult<()> {
+    match inv.name {
+        "ping" => ping::execute(ctx, inv).await,
+        "list" => list::execute(ctx, inv).await,
+        "start" => start::execute(ctx, inv).await,
+        "stop" => stop::execute(ctx, inv).await,
+        "restart" => restart::execute(ctx, inv).await,
+        "status" => status::execute(ctx, inv).await,
+        "schedule" => schedule::execute(ctx, inv).await,
+        "exec" => exec::execute(ctx, inv).await,
+        "usage" => usage::execute(ctx, inv).await,
+        "audit" => audit::execute(ctx, inv).await,
+        "resize" => resize::execute(ctx, inv).await,
+        "backup" => backup::execute_backup(ctx, inv).await,
+        "backups" => backup::execute_backups(ctx, inv).await,
+        "restore" => backup::execute_restore(ctx, inv).await,
+        "cancel" => start::cancel(ctx, inv).await,
+        _ => Ok(()),
+    }
+}
+
+pub async fn register_commands(ctx: &Context, guild: GuildId) -> SimpleResult<()> {
+    info!("Registering slash commands for guild {}.", guild);
+
+    guild
+        .set_application_commands(ctx, |commands| {
+            for cmd in COMMANDS {
+                commands.create_application_command(|c| {
+                    c.name(cmd.name).description(cmd.description);
+
+                    for opt in cmd.options {
+                        c.create_option(|o| {
+                            o.name(opt.name)
+                                .description(opt.description)
+                                .kind(ApplicationCommandOptionType::String)
+                                .required(opt.required);
+
+                            match opt.kind {
+                                SlashOptionKind::Instance
+                                | SlashOptionKind::Script
+                                | SlashOptionKind::Size => {
+                                    o.set_autocomplete(true);
+                                }
+                                SlashOptionKind::Choice(choices) => {
+                                    for choice in choices {
+                                        o.add_string_choice(choice, choice);
+                                    }
+                                }
+                                SlashOptionKind::Text => {}
+                            }
+
+                            o
+                        });
+                    }
+
+                    c
+                });
+            }
+
+            commands
+        })
+        .await?;
+
+    Ok(())
+}
+
+pub async fn handle_interaction(ctx: &Context, interaction: Interaction) {
+    match interaction {
+        Interaction::ApplicationCommand(ref cmd) => handle_command(ctx, cmd).await,
+        Interaction::Autocomplete(ref ac) => {
+            tri!(
+                handle_autocomplete(ctx, ac).await,
+                "Error responding to autocomplete interaction"
+            );
+        }
+        Interaction::MessageComponent(ref component) => handle_component(ctx, component).await,
+        _ => {}
+    }
+}
+
+async fn handle_command(ctx: &Context, interaction: &ApplicationCommandInteraction) {
+    let spec = match COMMANDS.iter().find(|c| c.name == interaction.data.name) {
+        Some(spec) => spec,
+        None => {
+            warn!("Received unknown slash command {}.", interaction.data.name);
+            return;
+        }
+    };
+
+    // Slash commands have to be acknowledged within 3 seconds
+    if let Err(why) = interaction.defer(ctx).await {
+        warn!("Error deferring interaction response: {:?}", why);
+        return;
+    }
+
+    let inv = Invocation::from_interaction(
+        interaction,
+        args(spec, |name| option_value(&interaction.data.options, name)),
+    );
+
+    if !authorize(ctx, &inv).await {
+        return;
+    }
+
+    finish(ctx, &inv, execute(ctx, &inv).await).await;
+}
+
+async fn handle_component(ctx: &Context, interaction: &MessageComponentInteraction) {
+    let (button, s_name) = match ProgressButton::parse(&interaction.data.custom_id) {
+        Some(parsed) => parsed,
+        None => {
+            warn!("Received unknown button {}.", interaction.data.custom_id);
+            return;
+        }
+    };
+
+    // Respond with a new message, instead of editing the one carrying the button
+    let res = interaction
+        .create_interaction_response(ctx, |r| {
+            r.kind(InteractionResponseType::DeferredChannelMessageWithSource)
+        })
+        .await;
+
+    if let Err(why) = res {
+        warn!("Error deferring interaction response: {:?}", why);
+        return;
+    }
+
+    let inv = Invocation::from_component(interaction, button.name(), vec![s_name.to_owned()]);
+
+    if !authorize(ctx, &inv).await {
+        return;
+    }
+
+    finish(ctx, &inv, execute(ctx, &inv).await).await;
+}
+
+async fn handle_autocomplete(
+    ctx: &Context,
+    interaction: &AutocompleteInteraction,
+) -> SimpleResult<()> {
+    let spec = match COMMANDS.iter().find(|c| c.name == interaction.data.name) {
+        Some(spec) => spec,
+        None => return Ok(()),
+    };
+
+    let focused = match interaction.data.options.iter().find(|o| o.focused) {
+        Some(focused) => focused,
+        None => return Ok(()),
+    };
+
+    let typed = focused
+        .value
+        .as_ref()
+        .and_then(Value::as_str)
+        .unwrap_or_default()
+        .to_lowercase();
+
+    let kind = match spec.options.iter().find(|o| o.name == focused.name) {
+        Some(opt) => &opt.kind,
+        None => return Ok(()),
+    };
+
+    let mut candidates = {
+        let data = ctx.data.read().await;
+        let servers = data.get::<ConfigKey>().unwrap().servers.snapshot();
+
+        let names = match kind {
+            SlashOptionKind::Instance => servers.keys().collect::<Vec<_>>(),
+            SlashOptionKind::Script => option_value(&interaction.data.options, INSTANCE.name)
+                .and_then(|s_name| servers.get(&s_name))
+                .map(|server_conf| server_conf.scripts.keys().collect())
+                .unwrap_or_default(),
+            SlashOptionKind::Size => option_value(&interaction.data.options, INSTANCE.name)
+                .and_then(|s_name| servers.get(&s_name))
+                .map(|server_conf| server_conf.sizes.iter().collect())
+                .unwrap_or_default(),
+            SlashOptionKind::Choice(_) | SlashOptionKind::Text => return Ok(()),
+        };
+
+        names
+            .into_iter()
+            .filter(|name| name.to_lowercase().starts_with(&typed))
+            .cloned()
+            .collect::<Vec<_>>()
+    };
+    candidates.sort();
+
+    // Only suggest what the user is allowed to use the command with
+    let mut choices = Vec::new();
+    for candidate in candidates {
+        let args = args(spec, |name| {
+            if name == focused.name {
+                Some(candidate.clone())
+            } else {
+                option_value(&interaction.data.options, name)
+            }
+        });
+
+        let inv = Invocation::from_autocomplete(interaction, args);
+
+        // Without all arguments the permission can't be checked, e.g. before the action of /exec is chosen
+        if matches!(has_permission(ctx, &inv).await, Ok(true)) {
+            choices.push(candidate);
+        }
+
+        if choices.len() == MAX_CHOICES {
+            break;
+        }
+    }
+
+    interaction
+        .create_autocomplete_response(ctx, |r| {
+            for choice in choices.iter() {
+                r.add_string_choice(choice, choice);
+            }
+            r
+        })
+        .await?;
+
+    Ok(())
+}
+
+/// Positional arguments in the order of the command specification, like for prefixed commands.
+///
+/// Omitted options followed by given ones are passed as [`OMITTED_ARG`], so the later ones keep their position.
+fn args(spec: &SlashCommand, value: impl Fn(&str) -> Option<String>) -> Vec<String> {
+    let values = spec
+        .options
+        .iter()
+        .map(|o| (o, value(o.name)))
+        .collect::<Vec<_>>();
+    let given = values
+        .iter()
+        .rposition(|(_, v)| v.is_some())
+        .map_or(0, |last| last + 1);
+
+    values
+        .into_iter()
+        .take(given)
+        .flat_map(|(o, v)| match (v, &o.kind) {
+            (None, _) => vec![OMITTED_ARG.to_owned()],
+            (Some(v), SlashOptionKind::Text) => {
+                v.split_whitespace().map(ToOwned::to_owned).collect()
+            }
+            (Some(v), _) => vec![v],
+        })
+        .collect()
+}
+
+fn option_value(options: &[ApplicationCommandInteractionDataOption], name: &str) -> Option<String> {
+    options
+        .iter()
+        .find(|o| o.name == name)
+        .and_then(|o| o.value.as_ref())
+        .map(|v| match v {
+            Value::String(s) => s.clone(),
+            other => other.to_string(),
+        })
+}
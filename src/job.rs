@@ -0,0 +1,381 @@
+use crate::azure::management::vm::PowerState;
+use crate::command::start::start_server;
+use crate::command::stop::stop_server;
+use crate::command::{progress, CancelKey, InstanceLockKey, ProgressMessage};
+use crate::conf::ConfigKey;
+use crate::provider::provider;
+use crate::sql::job::{in_state, set_state, set_step, JobRecord, NewJob};
+use crate::sql::usage::{self, NewVmRun};
+use crate::sql::uuid::Uuid;
+use crate::sql::{Sql, SqlKey};
+use crate::{AzureClientKey, SimpleError, SimpleResult};
+use chrono::Utc;
+use log::{error, info};
+use serenity::client::Context;
+use serenity::model::id::{ChannelId, UserId};
+use std::fmt::{Display, Formatter};
+use std::sync::Arc;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum JobKind {
+    Start,
+    Stop,
+    Exec,
+}
+
+impl JobKind {
+    fn as_str(self) -> &'static str {
+        match self {
+            JobKind::Start => "start",
+            JobKind::Stop => "stop",
+            JobKind::Exec => "exec",
+        }
+    }
+
+    fn parse(s: &str) -> Option<JobKind> {
+        [JobKind::Start, JobKind::Stop, JobKind::Exec]
+            .into_iter()
+            .find(|k| k.as_str() == s)
+    }
+}
+
+impl Display for JobKind {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        self.as_str().fmt(f)
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum JobStep {
+    Boot,
+    WaitForAgent,
+    StartScript,
+    StopScript,
+    Deallocate,
+    Script,
+}
+
+impl JobStep {
+    fn as_str(self) -> &'static str {
+        match self {
+            JobStep::Boot => "boot",
+            JobStep::WaitForAgent => "wait_for_agent",
+            JobStep::StartScript => "start_script",
+            JobStep::StopScript => "stop_script",
+            JobStep::Deallocate => "deallocate",
+            JobStep::Script => "script",
+        }
+    }
+}
+
+const RUNNING: &str = "running";
+const SUCCEEDED: &str = "succeeded";
+const FAILED: &str = "failed";
+
+/// Long-running operation on a server, persisted so it can be reconciled after a restart.
+pub struct Job {
+    id: Uuid,
+    server: String,
+    /// User requesting the job, `None` for jobs started by the bot itself.
+    user: Option<UserId>,
+    sql: Sql,
+}
+
+impl Job {
+    pub fn begin(
+        sql: &Sql,
+        server: &str,
+        kind: JobKind,
+        action: Option<&str>,
+        channel: Option<ChannelId>,
+        user: Option<UserId>,
+    ) -> SimpleResult<Job> {
+        let now = Utc::now().naive_utc();
+        let job = NewJob {
+            id: Uuid::random(),
+            server,
+            kind: kind.as_str(),
+            action,
+            step: "created",
+            state: RUNNING,
+            channel: channel.map(|c| c.0 as i64),
+            created_at: now,
+            updated_at: now,
+            user: user.map(|u| u.0 as i64),
+        };
+
+        job.insert(&mut sql.connection.get()?)?;
+
+        Ok(Job {
+            id: job.id,
+            server: server.to_owned(),
+            user,
+            sql: sql.clone(),
+        })
+    }
+
+    fn resume(sql: &Sql, record: &JobRecord) -> Job {
+        Job {
+            id: record.id,
+            server: record.server.clone(),
+            user: record.user.map(|u| UserId(u as u64)),
+            sql: sql.clone(),
+        }
+    }
+
+    /// Failing to persist the step is only logged, as it mustn't abort the job itself.
+    pub fn step(&self, step: JobStep) {
+        let res = self
+            .sql
+            .connection
+            .get()
+            .map_err(Into::into)
+            .and_then(|mut sql| set_step(&mut sql, self.id, step.as_str(), Utc::now().naive_utc()));
+
+        tri!(res, format!("Error persisting step of job {}", self.id));
+    }
+
+    /// Records the start of a vm run for the usage report.
+    pub fn vm_started(&self) {
+        let run = NewVmRun {
+            id: Uuid::random(),
+            server: &self.server,
+            started_by: self.user.map(|u| u.0 as i64),
+            started_at: Utc::now().naive_utc(),
+        };
+
+        let res = self
+            .sql
+            .connection
+            .get()
+            .map_err(Into::into)
+            .and_then(|mut sql| run.insert(&mut sql));
+
+        tri!(res, format!("Error recording start of {}", self.server));
+    }
+
+    /// Records the end of the open vm run for the usage report.
+    pub fn vm_stopped(&self) {
+        let res = self
+            .sql
+            .connection
+            .get()
+            .map_err(Into::into)
+            .and_then(|mut sql| {
+                usage::close(
+                    &mut sql,
+                    &self.server,
+                    self.user.map(|u| u.0 as i64),
+                    Utc::now().naive_utc(),
+                )
+            });
+
+        tri!(res, format!("Error recording stop of {}", self.server));
+    }
+
+    pub fn finish<T>(&self, res: &SimpleResult<T>) {
+        self.set_state(if res.is_ok() { SUCCEEDED } else { FAILED });
+    }
+
+    fn set_state(&self, state: &str) {
+        let res = self
+            .sql
+            .connection
+            .get()
+            .map_err(Into::into)
+            .and_then(|mut sql| set_state(&mut sql, self.id, state, Utc::now().naive_utc()));
+
+        tri!(res, format!("Error persisting state of job {}", self.id));
+    }
+}
+
+/// Finishes or fails the jobs interrupted by the last shutdown of the bot.
+pub fn spawn_reconciler(ctx: Arc<Context>) {
+    info!("Spawning job reconciler.");
+
+    tokio::spawn(async move {
+        let jobs = {
+            let data = ctx.data.read().await;
+            let sql = data.get::<SqlKey>().unwrap();
+            sql.connection
+                .get()
+                .map_err(SimpleError::from)
+                .and_then(|mut sql| in_state(&mut sql, RUNNING))
+        };
+
+        let jobs = match jobs {
+            Ok(jobs) => jobs,
+            Err(why) => {
+                error!("Error loading unfinished jobs: {}", why);
+                return;
+            }
+        };
+
+        for record in jobs {
+            let ctx = Arc::clone(&ctx);
+            tokio::spawn(async move {
+                if let Err(why) = reconcile(&ctx, &record).await {
+                    error!(
+                        "Reconciling the {} of {} failed: {}",
+                        record.kind, record.server, why
+                    );
+                }
+            });
+        }
+    });
+}
+
+async fn reconcile(ctx: &Context, record: &JobRecord) -> SimpleResult<()> {
+    let data = ctx.data.read().await;
+    let config = data.get::<ConfigKey>().unwrap();
+    let client = data.get::<AzureClientKey>().unwrap();
+    let locks = data.get::<InstanceLockKey>().unwrap();
+
+    let job = Job::resume(data.get::<SqlKey>().unwrap(), record);
+    let s_name = record.server.as_str();
+
+    info!(
+        "Reconciling the {} of {} started at {}, interrupted at step {} since {}.",
+        record.kind, s_name, record.created_at, record.step, record.updated_at
+    );
+
+    let mut progress = match record.channel {
+        Some(channel) => ProgressMessage::in_channel(
+            ChannelId(channel as u64),
+            format!(
+                "The bot restarted during the {} of **{}**:",
+                record.kind, s_name
+            ),
+        ),
+        None => ProgressMessage::silent(),
+    };
+
+    let servers = config.servers.snapshot();
+    let (kind, server_conf) = match (JobKind::parse(&record.kind), servers.get(s_name)) {
+        (Some(kind), Some(server_conf)) => (kind, server_conf),
+        _ => {
+            job.set_state(FAILED);
+            progress!(progress, ctx, "The server or job isn't known anymore.");
+            return Ok(());
+        }
+    };
+
+    // Wait for tasks started since the restart, instead of interfering with them
+    let _l = locks.get(s_name).await.lock_owned().await;
+
+    let provider = provider(client, server_conf);
+    let power_state = provider.status().await?.power_state;
+
+    let res = match kind {
+        JobKind::Start if power_state == PowerState::Deallocated => {
+            job.set_state(FAILED);
+            progress!(
+                progress,
+                ctx,
+                "The server is deallocated, the start failed."
+            );
+            Ok(())
+        }
+        // Powered off servers are still billed until they're deallocated
+        JobKind::Start
+            if matches!(
+                power_state,
+                PowerState::Stopping | PowerState::Stopped | PowerState::Deallocating
+            ) =>
+        {
+            job.set_state(FAILED);
+            progress!(
+                progress,
+                ctx,
+                format!(
+                    "The server is {}, the start failed. Deallocating it ...",
+                    power_state
+                )
+            );
+
+            let res = provider.power_off().await;
+            if res.is_ok() {
+                job.vm_stopped();
+                progress!(progress, ctx, "Deallocated the server.");
+            }
+            res
+        }
+        // Like exec scripts, start scripts aren't necessarily idempotent
+        JobKind::Start if record.step == JobStep::StartScript.as_str() => {
+            job.set_state(FAILED);
+            progress!(
+                progress,
+                ctx,
+                "The start script might not have finished, please check the server."
+            );
+            Ok(())
+        }
+        // The start script hasn't run yet. Booting a running server is a no-op and its open run isn't
+        // recorded twice, so the start is safe to repeat.
+        JobKind::Start => {
+            let cancel = data.get::<CancelKey>().unwrap().register(s_name);
+            start_server(
+                ctx,
+                client,
+                s_name,
+                server_conf,
+                &mut progress,
+                &cancel,
+                &job,
+            )
+            .await
+        }
+        JobKind::Stop if power_state == PowerState::Deallocated => {
+            job.set_state(SUCCEEDED);
+            progress!(
+                progress,
+                ctx,
+                "The server is deallocated, the stop succeeded."
+            );
+            Ok(())
+        }
+        JobKind::Stop if power_state == PowerState::Running => {
+            stop_server(ctx, client, s_name, server_conf, &mut progress, &job).await
+        }
+        // The stop script can't run anymore, but the server mustn't stay allocated
+        JobKind::Stop => {
+            job.step(JobStep::Deallocate);
+            progress!(
+                progress,
+                ctx,
+                format!("The server is {}, powering it off ...", power_state)
+            );
+
+            let res = provider.power_off().await;
+            if res.is_ok() {
+                job.vm_stopped();
+                progress!(progress, ctx, "Stopped the server.");
+            }
+            job.finish(&res);
+            res
+        }
+        // Maintenance scripts aren't necessarily idempotent, so they're never run twice
+        JobKind::Exec => {
+            job.set_state(FAILED);
+            progress!(
+                progress,
+                ctx,
+                format!(
+                    "The script {} might not have finished, please check the server.",
+                    record.action.as_deref().unwrap_or_default()
+                )
+            );
+            Ok(())
+        }
+    };
+
+    match res {
+        // The progress message already tells about the cancellation
+        Err(SimpleError::Cancelled) => Ok(()),
+        Err(ref why) => {
+            progress!(progress, ctx, format!("Failed: {}", why));
+            res
+        }
+        Ok(()) => Ok(()),
+    }
+}
@@ -4,24 +4,46 @@ extern crate diesel;
 #[macro_use]
 mod macros;
 
+mod audit;
 mod azure;
+mod budget;
 mod command;
 mod conf;
+mod dependency;
+mod discovery;
 mod handler;
 mod hook;
+mod idle;
+mod interaction;
+mod job;
 mod movie;
 mod owners;
 mod permission;
+mod provider;
+mod quota;
+mod schedule;
 mod schema;
 mod sql;
+#[cfg(test)]
+mod testing;
 mod voice;
 
 use crate::azure::authentication::{load_cert, load_priv_key};
+use crate::azure::management::vm_run_cmd::ScriptOutput;
 use crate::azure::{new_azure_client, AzureClientKey};
+use crate::command::audit::AUDIT_COMMAND;
+use crate::command::backup::{BACKUPS_COMMAND, BACKUP_COMMAND, RESTORE_COMMAND};
+use crate::command::exec::EXEC_COMMAND;
+use crate::command::list::LIST_COMMAND;
 use crate::command::ping::PING_COMMAND;
+use crate::command::resize::RESIZE_COMMAND;
+use crate::command::restart::RESTART_COMMAND;
+use crate::command::schedule::SCHEDULE_COMMAND;
 use crate::command::start::START_COMMAND;
+use crate::command::status::STATUS_COMMAND;
 use crate::command::stop::STOP_COMMAND;
-use crate::command::{InstanceLockKey, CMD_PREFIX};
+use crate::command::usage::USAGE_COMMAND;
+use crate::command::{CancelKey, InstanceLockKey, CMD_PREFIX};
 use crate::conf::{ConfigKey, Settings};
 use crate::handler::Handler;
 use crate::hook::{after_hook, before_hook};
@@ -55,8 +77,20 @@ pub enum SimpleError {
     JwtError(#[from] jwt::Error),
     #[error("Serde Error: {}", .0)]
     SerdeError(#[from] serde_json::Error),
+    #[error("Authentication failed: {}", .0)]
+    AuthenticationFailed(String),
     #[error("Timeout")]
     Timeout,
+    #[error("Cancelled")]
+    Cancelled,
+    #[error("Provisioning ended in state {}", .0)]
+    ProvisioningFailed(String),
+    #[error("Unexpected response from azure: {}", .0)]
+    UnexpectedResponse(String),
+    #[error("{}", .0.summary())]
+    ScriptFailed(Box<ScriptOutput>),
+    #[error("Autocomplete interactions can't be replied to")]
+    NotReplyable,
     #[error("TCP connection not established")]
     NotConnected,
     #[error("Error parsing header: {}", .0)]
@@ -82,7 +116,10 @@ impl From<HttpError> for SimpleError {
 pub type SimpleResult<T> = Result<T, SimpleError>;
 
 #[group]
-#[commands(ping, start, stop)]
+#[commands(
+    ping, list, start, stop, restart, status, schedule, exec, usage, audit, resize, backup,
+    backups, restore
+)]
 #[only_in(guilds)]
 struct General;
 
@@ -120,6 +157,7 @@ async fn main() {
         data.insert::<ConfigKey>(config);
         data.insert::<RbacKey>(RbacManager::new().expect("Error creating rbac manager."));
         data.insert::<InstanceLockKey>(Default::default());
+        data.insert::<CancelKey>(Default::default());
         data.insert::<SqlKey>(sql);
     })
     .await;
@@ -1,13 +1,46 @@
 pub mod rbac;
 
+use crate::SimpleResult;
 use async_trait::async_trait;
 use serenity::client::Context;
+use serenity::model::id::{GuildId, RoleId, UserId};
 
 #[async_trait]
 pub trait HasPermission<P> {
     async fn has_permission(&self, ctx: &Context, perm: &P) -> bool;
 }
 
+/// Checks the permission of the user itself and of all discord roles the user has in the guild.
+pub async fn check_permission<P>(
+    ctx: &Context,
+    guild: Option<GuildId>,
+    user: UserId,
+    perm: &P,
+) -> SimpleResult<bool>
+where
+    P: Sync,
+    UserId: HasPermission<P>,
+    RoleId: HasPermission<P>,
+{
+    if user.has_permission(ctx, perm).await {
+        return Ok(true);
+    }
+
+    let guild = match guild {
+        Some(g) => g,
+        None => return Ok(false),
+    };
+
+    let member = guild.member(ctx, user).await?;
+    for r in member.roles.iter() {
+        if r.has_permission(ctx, perm).await {
+            return Ok(true);
+        }
+    }
+
+    Ok(false)
+}
+
 macro_rules! _has_permission {
     ($id:expr, $ctx:expr, $p:expr) => {{
         let data = $ctx.data.read().await;
@@ -75,6 +75,19 @@ impl RbacManager {
 
         Ok(RbacManager { u2r, g2r, r2p })
     }
+
+    /// All roles of the user, including the default role and those of its discord roles.
+    pub fn roles_of<'a>(&'a self, user: UserId, discord_roles: &[RoleId]) -> Vec<&'a Role> {
+        let mut roles = vec![&*DEFAULT_ROLE];
+        roles.extend(self.u2r.get(&user).into_iter().flatten());
+        roles.extend(
+            discord_roles
+                .iter()
+                .filter_map(|r| self.g2r.get(r))
+                .flatten(),
+        );
+        roles
+    }
 }
 
 pub struct RbacKey;
@@ -0,0 +1,57 @@
+pub mod azure;
+pub mod local;
+
+use crate::azure::management::vm::PowerState;
+use crate::azure::management::vm_run_cmd::{RunCommandParameter, ScriptOutput};
+use crate::azure::AzureClient;
+use crate::conf::{ProviderConfig, ServerConfig};
+use crate::provider::azure::AzureVm;
+use crate::provider::local::LocalCommand;
+use crate::SimpleResult;
+use async_trait::async_trait;
+use chrono::{DateTime, Utc};
+use std::time::Duration;
+
+/// Compute backing a server, which can be powered on and off and runs its scripts.
+///
+/// Providers without a distinction between stopped and deallocated report `Deallocated` once powered off.
+#[async_trait]
+pub trait ComputeProvider: Send + Sync {
+    async fn status(&self) -> SimpleResult<ServerStatus>;
+    /// Boots the server, returns once it is powered on.
+    async fn power_on(&self, timeout: Duration) -> SimpleResult<()>;
+    async fn power_off(&self) -> SimpleResult<()>;
+    async fn restart(&self, timeout: Duration) -> SimpleResult<()> {
+        self.power_off().await?;
+        self.power_on(timeout).await
+    }
+    /// Runs the shell script, the parameters are passed as environment variables.
+    async fn run_script(
+        &self,
+        script: &str,
+        parameters: Vec<RunCommandParameter>,
+        timeout: Duration,
+    ) -> SimpleResult<ScriptOutput>;
+}
+
+#[derive(Debug, Clone)]
+pub struct ServerStatus {
+    pub power_state: PowerState,
+    /// Whether scripts can be run on the server.
+    pub ready: bool,
+    /// Last change of the power state, if the provider knows it.
+    pub changed_at: Option<DateTime<Utc>>,
+    /// Provider specific lines shown by `~status`.
+    pub details: Vec<String>,
+}
+
+/// Provider of the server, vms are managed with the given client.
+pub fn provider<'a>(
+    client: &'a AzureClient,
+    server_conf: &'a ServerConfig,
+) -> Box<dyn ComputeProvider + 'a> {
+    match server_conf.provider {
+        ProviderConfig::Vm(ref vm) => Box::new(AzureVm::new(client, vm)),
+        ProviderConfig::Local(ref local) => Box::new(LocalCommand::new(local)),
+    }
+}
@@ -0,0 +1,116 @@
+use crate::azure::management::vm::VmClient;
+use crate::azure::management::vm_run_cmd::{
+    RunCommandParameter, ScriptOutput, ShellCommand, VmRunCmdClient,
+};
+use crate::azure::AzureClient;
+use crate::command::status::format_duration;
+use crate::conf::VmConfig;
+use crate::provider::{ComputeProvider, ServerStatus};
+use crate::SimpleResult;
+use async_trait::async_trait;
+use std::time::Duration;
+
+/// Azure vm, scripts are executed by its run command extension.
+pub struct AzureVm<'a> {
+    client: &'a AzureClient,
+    vm: &'a VmConfig,
+}
+
+impl<'a> AzureVm<'a> {
+    pub fn new(client: &'a AzureClient, vm: &'a VmConfig) -> Self {
+        AzureVm { client, vm }
+    }
+}
+
+#[async_trait]
+impl ComputeProvider for AzureVm<'_> {
+    async fn status(&self) -> SimpleResult<ServerStatus> {
+        let view = self
+            .client
+            .instance_view(&self.vm.sub, &self.vm.rg, &self.vm.name)
+            .await?;
+
+        let mut details = Vec::new();
+
+        if let Some(provisioning) = view.provisioning_state() {
+            details.push(format!("Provisioning: {}", provisioning.display_status));
+        }
+
+        match view.agent_status() {
+            Some(agent) => details.push(format!(
+                "Agent: {} ({})",
+                agent.display_status,
+                view.vm_agent
+                    .as_ref()
+                    .and_then(|a| a.vm_agent_version.as_deref())
+                    .unwrap_or("unknown version")
+            )),
+            None => details.push("Agent: not reporting".to_owned()),
+        }
+
+        if let Some(ref os) = view.os_name {
+            details.push(format!(
+                "OS: {} {}",
+                os,
+                view.os_version.as_deref().unwrap_or_default()
+            ));
+        }
+
+        if let Some(uptime) = view.uptime() {
+            details.push(format!("Uptime: {}", format_duration(uptime)));
+        }
+
+        Ok(ServerStatus {
+            power_state: view.power_state(),
+            ready: view.agent_ready(),
+            // The last operation on the vm is the best guess for when it was started or stopped
+            changed_at: view.provisioning_state().and_then(|s| s.time),
+            details,
+        })
+    }
+
+    async fn power_on(&self, timeout: Duration) -> SimpleResult<()> {
+        self.client
+            .start(&self.vm.sub, &self.vm.rg, &self.vm.name)
+            .await?
+            .timeout(Some(timeout))
+            .wait()
+            .await
+    }
+
+    async fn power_off(&self) -> SimpleResult<()> {
+        self.client
+            .deallocate(&self.vm.sub, &self.vm.rg, &self.vm.name)
+            .await?
+            .wait()
+            .await
+    }
+
+    async fn restart(&self, timeout: Duration) -> SimpleResult<()> {
+        self.client
+            .restart(&self.vm.sub, &self.vm.rg, &self.vm.name)
+            .await?
+            .timeout(Some(timeout))
+            .wait()
+            .await
+    }
+
+    async fn run_script(
+        &self,
+        script: &str,
+        parameters: Vec<RunCommandParameter>,
+        timeout: Duration,
+    ) -> SimpleResult<ScriptOutput> {
+        let script = ShellCommand {
+            script: [script],
+            parameters,
+        };
+
+        self.client
+            .run(&self.vm.sub, &self.vm.rg, &self.vm.name, script)
+            .await?
+            .timeout(Some(timeout))
+            .wait()
+            .await
+    }
+}
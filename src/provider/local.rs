@@ -0,0 +1,236 @@
+use crate::azure::management::vm::PowerState;
+use crate::azure::management::vm_run_cmd::{RunCommandParameter, ScriptOutput};
+use crate::conf::LocalConfig;
+use crate::provider::{ComputeProvider, ServerStatus};
+use crate::{SimpleError, SimpleResult};
+use async_trait::async_trait;
+use std::process::Stdio;
+use std::time::Duration;
+use tokio::io::AsyncWriteExt;
+use tokio::process::Command;
+use tokio::time::sleep;
+
+const COMMAND_TIMEOUT: Duration = Duration::from_secs(60);
+const POWER_OFF_TIMEOUT: Duration = Duration::from_secs(300);
+const POLL_INTERVAL: Duration = Duration::from_secs(5);
+
+/// Server managed by shell commands on the bot host.
+pub struct LocalCommand<'a> {
+    conf: &'a LocalConfig,
+}
+
+impl<'a> LocalCommand<'a> {
+    pub fn new(conf: &'a LocalConfig) -> Self {
+        LocalCommand { conf }
+    }
+
+    async fn powered_on(&self) -> SimpleResult<bool> {
+        Ok(shell(&self.conf.status, None, COMMAND_TIMEOUT)
+            .await?
+            .succeeded())
+    }
+
+    /// Polls the status command until the server reached the power state.
+    async fn wait_for(&self, on: bool) -> SimpleResult<()> {
+        while self.powered_on().await? != on {
+            sleep(POLL_INTERVAL).await;
+        }
+
+        Ok(())
+    }
+}
+
+#[async_trait]
+impl ComputeProvider for LocalCommand<'_> {
+    async fn status(&self) -> SimpleResult<ServerStatus> {
+        let on = self.powered_on().await?;
+
+        let ready = match self.conf.ready {
+            Some(ref ready) if on => shell(ready, None, COMMAND_TIMEOUT).await?.succeeded(),
+            _ => on,
+        };
+
+        Ok(ServerStatus {
+            power_state: if on {
+                PowerState::Running
+            } else {
+                PowerState::Deallocated
+            },
+            ready,
+            changed_at: None,
+            details: vec![format!("Ready: {}", if ready { "yes" } else { "no" })],
+        })
+    }
+
+    async fn power_on(&self, timeout: Duration) -> SimpleResult<()> {
+        tokio::time::timeout(timeout, async {
+            shell(&self.conf.power_on, None, timeout)
+                .await?
+                .into_result()?;
+            self.wait_for(true).await
+        })
+        .await
+        .map_err(|_| SimpleError::Timeout)?
+    }
+
+    async fn power_off(&self) -> SimpleResult<()> {
+        tokio::time::timeout(POWER_OFF_TIMEOUT, async {
+            shell(&self.conf.power_off, None, POWER_OFF_TIMEOUT)
+                .await?
+                .into_result()?;
+            self.wait_for(false).await
+        })
+        .await
+        .map_err(|_| SimpleError::Timeout)?
+    }
+
+    async fn run_script(
+        &self,
+        script: &str,
+        parameters: Vec<RunCommandParameter>,
+        timeout: Duration,
+    ) -> SimpleResult<ScriptOutput> {
+        // Exported by the script itself, so they also reach runners like `ssh host sh -s`
+        let mut input = parameters
+            .iter()
+            .map(|p| format!("export {}='{}'\n", p.name, p.value.replace('\'', r"'\''")))
+            .collect::<String>();
+        input.push_str(script);
+
+        let runner = self.conf.script_runner.as_deref().unwrap_or("sh -s");
+        shell(runner, Some(&input), timeout).await
+    }
+}
+
+/// Runs the command line by `sh -c`, the input is passed on stdin.
+async fn shell(
+    command: &str,
+    input: Option<&str>,
+    timeout: Duration,
+) -> SimpleResult<ScriptOutput> {
+    let mut child = Command::new("sh")
+        .arg("-c")
+        .arg(command)
+        .stdin(if input.is_some() {
+            Stdio::piped()
+        } else {
+            Stdio::null()
+        })
+        .stdout(Stdio::piped())
+        .stderr(Stdio::piped())
+        .kill_on_drop(true)
+        .spawn()?;
+
+    let stdin = child.stdin.take();
+    let write = async move {
+        if let (Some(mut stdin), Some(input)) = (stdin, input) {
+            // Commands may exit without reading their input, which isn't an error
+            let _ = stdin.write_all(input.as_bytes()).await;
+        }
+    };
+
+    let (_, output) = tokio::time::timeout(timeout, async {
+        tokio::join!(write, child.wait_with_output())
+    })
+    .await
+    .map_err(|_| SimpleError::Timeout)?;
+    let output = output?;
+
+    Ok(ScriptOutput {
+        stdout: String::from_utf8_lossy(&output.stdout).into_owned(),
+        stderr: String::from_utf8_lossy(&output.stderr).into_owned(),
+        exit_status: if output.status.success() {
+            None
+        } else {
+            output.status.code()
+        },
+        error: output
+            .status
+            .code()
+            .is_none()
+            .then(|| "Terminated by a signal".to_owned()),
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use uuid::Uuid;
+
+    /// Server powered on while the marker file exists.
+    fn marker_config(power_on: &str) -> LocalConfig {
+        let marker = std::env::temp_dir()
+            .join(format!("r6v3-{}.on", Uuid::new_v4()))
+            .display()
+            .to_string();
+
+        LocalConfig {
+            power_on: power_on.replace("{marker}", &marker),
+            power_off: format!("rm -f {}", marker),
+            status: format!("test -e {}", marker),
+            ready: None,
+            script_runner: None,
+        }
+    }
+
+    #[tokio::test]
+    async fn powers_on_and_off() {
+        let conf = marker_config("touch {marker}");
+        let local = LocalCommand::new(&conf);
+
+        local.power_on(Duration::from_secs(10)).await.unwrap();
+        let status = local.status().await.unwrap();
+        assert_eq!(status.power_state, PowerState::Running);
+        assert!(status.ready);
+
+        local.power_off().await.unwrap();
+        assert_eq!(
+            local.status().await.unwrap().power_state,
+            PowerState::Deallocated
+        );
+    }
+
+    #[tokio::test]
+    async fn failing_command_is_script_failure() {
+        let conf = marker_config("echo broken >&2; exit 3");
+        let local = LocalCommand::new(&conf);
+
+        match local.power_on(Duration::from_secs(10)).await {
+            Err(SimpleError::ScriptFailed(output)) => {
+                assert_eq!(output.exit_status, Some(3));
+                assert_eq!(output.stderr.trim(), "broken");
+            }
+            other => panic!("Expected a failed script, got {:?}", other),
+        }
+    }
+
+    #[tokio::test]
+    async fn slow_script_times_out() {
+        let conf = marker_config("touch {marker}");
+        let local = LocalCommand::new(&conf);
+
+        let res = local
+            .run_script("sleep 5", Vec::new(), Duration::from_millis(100))
+            .await;
+
+        assert!(matches!(res, Err(SimpleError::Timeout)), "{:?}", res);
+    }
+
+    #[tokio::test]
+    async fn script_gets_parameters() {
+        let conf = marker_config("touch {marker}");
+        let local = LocalCommand::new(&conf);
+        let parameters = vec![RunCommandParameter {
+            name: "GREETING".to_owned(),
+            value: "it's me".to_owned(),
+        }];
+
+        let output = local
+            .run_script("echo \"$GREETING\"", parameters, COMMAND_TIMEOUT)
+            .await
+            .unwrap();
+
+        assert!(output.succeeded());
+        assert_eq!(output.stdout.trim(), "it's me");
+    }
+}
@@ -0,0 +1,90 @@
+use crate::command::{usage_error, Invocation};
+use crate::conf::{ConfigKey, QuotaConfig};
+use crate::dependency::dependents;
+use crate::owners::Owners;
+use crate::permission::rbac::{RbacKey, RbacManager};
+use crate::sql::usage::open_runs_started_by;
+use crate::sql::SqlKey;
+use crate::SimpleResult;
+use serenity::client::Context;
+use serenity::model::id::{RoleId, UserId};
+use serenity::prelude::TypeMap;
+
+/// Servers the user may have running at once, None if unlimited.
+pub fn user_limit(
+    quotas: &QuotaConfig,
+    rbac: &RbacManager,
+    user: UserId,
+    discord_roles: &[RoleId],
+) -> Option<usize> {
+    if let Some(&limit) = quotas.users.get(&user) {
+        return Some(limit);
+    }
+
+    rbac.roles_of(user, discord_roles)
+        .into_iter()
+        .filter_map(|role| quotas.roles.get(role))
+        .copied()
+        .max()
+}
+
+/// Fails with a usage error, if starting the server would exceed the quota of the invoking user.
+///
+/// Owners are exempt, only servers started by the user itself count.
+pub async fn check_quota(
+    ctx: &Context,
+    data: &TypeMap,
+    inv: &Invocation<'_>,
+    s_name: &str,
+) -> SimpleResult<()> {
+    let config = data.get::<ConfigKey>().unwrap();
+    let quotas = &config.quotas;
+
+    if quotas.is_empty() || data.get::<Owners>().unwrap().contains(&inv.user.id) {
+        return Ok(());
+    }
+
+    let discord_roles = match inv.guild_id {
+        Some(guild) => guild.member(ctx, inv.user.id).await?.roles,
+        None => Vec::new(),
+    };
+
+    let rbac = data.get::<RbacKey>().unwrap();
+    let limit = match user_limit(quotas, rbac, inv.user.id, &discord_roles) {
+        Some(limit) => limit,
+        None => return Ok(()),
+    };
+
+    let sql = data.get::<SqlKey>().unwrap();
+    let open = open_runs_started_by(&mut sql.connection.get()?, inv.user.id.0 as i64)?;
+
+    let servers = config.servers.snapshot();
+
+    // Dependencies are started along with the server needing them and don't count on their own
+    let running = open
+        .iter()
+        .filter(|s| *s != s_name)
+        .filter(|s| {
+            !dependents(&servers, s)
+                .iter()
+                .any(|d| open.iter().any(|o| o.as_str() == *d))
+        })
+        .cloned()
+        .collect::<Vec<_>>();
+
+    if running.len() < limit {
+        return Ok(());
+    }
+
+    if running.is_empty() {
+        return Err(usage_error!(
+            "You are not allowed to start servers on your own."
+        ));
+    }
+
+    Err(usage_error!(
+        "You may only have {} server(s) running at once, stop one of yours first: {}",
+        limit,
+        running.join(", ")
+    ))
+}
@@ -0,0 +1,204 @@
+use crate::azure::management::vm::PowerState;
+use crate::budget::used_hours;
+use crate::command::start::start_server;
+use crate::command::usage::current_month;
+use crate::command::{progress, CancelKey, InstanceLockKey, ProgressMessage};
+use crate::conf::{ConfigKey, ServerConfig};
+use crate::dependency::{start_dependencies, stop_with_dependencies};
+use crate::job::{Job, JobKind};
+use crate::provider::provider;
+use crate::sql::schedule::is_paused;
+use crate::sql::SqlKey;
+use crate::{AzureClientKey, SimpleError, SimpleResult};
+use chrono::{DateTime, Utc};
+use log::{error, info};
+use serenity::client::Context;
+use serenity::model::id::ChannelId;
+use std::fmt::{Display, Formatter};
+use std::sync::Arc;
+use std::time::Duration;
+use tokio::time::sleep;
+
+const SCHEDULE_CHECK_INTERVAL: Duration = Duration::from_secs(30);
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ScheduledAction {
+    Start,
+    Stop,
+}
+
+impl Display for ScheduledAction {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        match self {
+            ScheduledAction::Start => "start".fmt(f),
+            ScheduledAction::Stop => "stop".fmt(f),
+        }
+    }
+}
+
+/// Next time the action is due according to the server's schedule.
+pub fn next_run(
+    server_conf: &ServerConfig,
+    action: ScheduledAction,
+    after: DateTime<Utc>,
+) -> Option<DateTime<Utc>> {
+    let schedule = server_conf.schedule.as_ref()?;
+    let cron = match action {
+        ScheduledAction::Start => schedule.start.as_ref()?,
+        ScheduledAction::Stop => schedule.stop.as_ref()?,
+    };
+
+    cron.after(&after.with_timezone(&schedule.timezone))
+        .next()
+        .map(|t| t.with_timezone(&Utc))
+}
+
+pub fn spawn_scheduler(ctx: Arc<Context>) {
+    info!("Spawning scheduler.");
+
+    tokio::spawn(async move { scheduler(ctx).await });
+}
+
+async fn scheduler(ctx: Arc<Context>) {
+    let mut last_check = Utc::now();
+
+    loop {
+        sleep(SCHEDULE_CHECK_INTERVAL).await;
+
+        let now = Utc::now();
+
+        let due = {
+            let data = ctx.data.read().await;
+            let config = data.get::<ConfigKey>().unwrap();
+
+            let mut due = Vec::new();
+            for (s_name, server_conf) in config.servers.snapshot().iter() {
+                for action in [ScheduledAction::Start, ScheduledAction::Stop] {
+                    if matches!(next_run(server_conf, action, last_check), Some(t) if t <= now) {
+                        due.push((s_name.clone(), server_conf.clone(), action));
+                    }
+                }
+            }
+
+            due
+        };
+
+        last_check = now;
+
+        for (s_name, server_conf, action) in due {
+            let ctx = Arc::clone(&ctx);
+            tokio::spawn(async move {
+                if let Err(why) = run_scheduled(&ctx, &s_name, &server_conf, action).await {
+                    error!("Scheduled {} of {} failed: {}", action, s_name, why);
+                }
+            });
+        }
+    }
+}
+
+async fn run_scheduled(
+    ctx: &Context,
+    s_name: &str,
+    server_conf: &ServerConfig,
+    action: ScheduledAction,
+) -> SimpleResult<()> {
+    let data = ctx.data.read().await;
+    let client = data.get::<AzureClientKey>().unwrap();
+    let locks = data.get::<InstanceLockKey>().unwrap();
+
+    {
+        let mut sql = data.get::<SqlKey>().unwrap().connection.get()?;
+        if is_paused(&mut sql, s_name)? {
+            info!("Schedule of {} is paused, skipping {}.", s_name, action);
+            return Ok(());
+        }
+    }
+
+    let mut progress = match server_conf.announce_channel {
+        Some(channel) => ProgressMessage::in_channel(
+            ChannelId(channel),
+            format!("Scheduled {} of **{}**:", action, s_name),
+        ),
+        None => ProgressMessage::silent(),
+    };
+
+    let _l = match locks.get(s_name).await.try_lock_owned() {
+        Ok(l) => l,
+        Err(_) => {
+            info!(
+                "Scheduled {} of {} blocked by another task, skipping it.",
+                action, s_name
+            );
+            progress!(progress, ctx, "Skipped, blocked by another task.");
+            return Ok(());
+        }
+    };
+
+    let power_state = provider(client, server_conf).status().await?.power_state;
+
+    let skip = match action {
+        ScheduledAction::Start => power_state == PowerState::Running,
+        ScheduledAction::Stop => {
+            matches!(power_state, PowerState::Deallocated | PowerState::Stopped)
+        }
+    };
+
+    if skip {
+        info!(
+            "Skipping scheduled {} of {}, the server is already {}.",
+            action, s_name, power_state
+        );
+        return Ok(());
+    }
+
+    let sql = data.get::<SqlKey>().unwrap();
+
+    if let (ScheduledAction::Start, Some(budget)) = (action, server_conf.budget.as_ref()) {
+        if used_hours(sql, s_name, current_month())? >= budget.hours {
+            info!(
+                "Skipping scheduled start of {}, its budget is used up.",
+                s_name
+            );
+            progress!(progress, ctx, "Skipped, the monthly budget is used up.");
+            return Ok(());
+        }
+    }
+
+    info!("Executing scheduled {} of {}.", action, s_name);
+
+    let res = match action {
+        ScheduledAction::Start => {
+            async {
+                let cancel = data.get::<CancelKey>().unwrap().register(s_name);
+                start_dependencies(ctx, &data, s_name, &mut progress, &cancel, None).await?;
+
+                let job = Job::begin(sql, s_name, JobKind::Start, None, progress.channel(), None)?;
+                start_server(
+                    ctx,
+                    client,
+                    s_name,
+                    server_conf,
+                    &mut progress,
+                    &cancel,
+                    &job,
+                )
+                .await
+            }
+            .await
+        }
+        ScheduledAction::Stop => {
+            stop_with_dependencies(ctx, &data, s_name, server_conf, &mut progress, None).await
+        }
+    };
+
+    match res {
+        // The progress message already tells about the cancellation
+        Err(SimpleError::Cancelled) => return Ok(()),
+        Err(ref why) => {
+            progress!(progress, ctx, format!("Failed: {}", why));
+        }
+        Ok(()) => {}
+    }
+
+    res
+}
@@ -1,3 +1,35 @@
+table! {
+    audit_log (id) {
+        id -> Binary,
+        user -> BigInt,
+        user_tag -> Text,
+        guild -> Nullable<BigInt>,
+        channel -> BigInt,
+        command -> Text,
+        arguments -> Text,
+        instance -> Nullable<Text>,
+        permitted -> Bool,
+        error -> Nullable<Text>,
+        duration_ms -> BigInt,
+        created_at -> Timestamp,
+    }
+}
+
+table! {
+    jobs (id) {
+        id -> Binary,
+        server -> Text,
+        kind -> Text,
+        action -> Nullable<Text>,
+        step -> Text,
+        state -> Text,
+        channel -> Nullable<BigInt>,
+        created_at -> Timestamp,
+        updated_at -> Timestamp,
+        user -> Nullable<BigInt>,
+    }
+}
+
 table! {
     movie_channels (id) {
         id -> Binary,
@@ -10,3 +42,24 @@ table! {
         created_at -> Timestamp,
     }
 }
+
+table! {
+    schedule_pauses (server) {
+        server -> Text,
+        paused_by -> BigInt,
+        paused_at -> Timestamp,
+    }
+}
+
+table! {
+    vm_runs (id) {
+        id -> Binary,
+        server -> Text,
+        started_by -> Nullable<BigInt>,
+        started_at -> Timestamp,
+        stopped_by -> Nullable<BigInt>,
+        stopped_at -> Nullable<Timestamp>,
+    }
+}
+
+allow_tables_to_appear_in_same_query!(audit_log, jobs, movie_channels, schedule_pauses, vm_runs,);
@@ -1,8 +1,11 @@
+pub mod audit;
+pub mod job;
 pub mod movie;
+pub mod schedule;
+pub mod usage;
 pub mod uuid;
 
 use crate::SimpleResult;
-use diesel::connection::Connection;
 use diesel::r2d2::{ConnectionManager, Pool};
 use diesel::sqlite::SqliteConnection;
 use diesel_migrations::EmbeddedMigrations;
@@ -22,15 +25,20 @@ pub struct Sql {
 impl Sql {
     pub fn new() -> SimpleResult<Self> {
         let db = env::var("DATABASE_URL").expect("DATABASE_URL must be set.");
-        let mut con = SqliteConnection::establish(&db).unwrap();
-        con.run_pending_migrations(MIGRATIONS)
-            .expect("DB migrations failed");
-        let manager: ConnectionManager<SqliteConnection> = ConnectionManager::new(&db);
+        Self::open(&db)
+    }
+
+    /// Migrations run on a pooled connection, so shared in-memory databases are migrated as well.
+    pub fn open(db: &str) -> SimpleResult<Self> {
+        let manager: ConnectionManager<SqliteConnection> = ConnectionManager::new(db);
         let r2d2 = diesel::r2d2::Pool::builder()
             .max_size(10)
             .connection_timeout(DB_CON_TIMEOUT)
             .build(manager)
             .expect("Failed to initialize connection pool.");
+        r2d2.get()?
+            .run_pending_migrations(MIGRATIONS)
+            .expect("DB migrations failed");
         Ok(Self { connection: r2d2 })
     }
 }
@@ -0,0 +1,79 @@
+use super::uuid::Uuid;
+use crate::schema::audit_log;
+use crate::schema::audit_log::dsl;
+use crate::{SimpleError, SimpleResult};
+use chrono::NaiveDateTime;
+use diesel::prelude::*;
+use diesel::sqlite::Sqlite;
+use diesel::{Insertable, Queryable, SqliteConnection};
+
+#[derive(Insertable, Debug, Clone, Copy)]
+#[diesel(table_name = audit_log)]
+pub struct NewAuditEntry<'a> {
+    pub id: Uuid,
+    pub user: i64,
+    pub user_tag: &'a str,
+    pub guild: Option<i64>,
+    pub channel: i64,
+    pub command: &'a str,
+    pub arguments: &'a str,
+    pub instance: Option<&'a str>,
+    pub permitted: bool,
+    pub error: Option<&'a str>,
+    pub duration_ms: i64,
+    pub created_at: NaiveDateTime,
+}
+
+impl NewAuditEntry<'_> {
+    pub fn insert(&self, sql: &mut SqliteConnection) -> SimpleResult<()> {
+        diesel::insert_into(dsl::audit_log)
+            .values(self)
+            .execute(sql)
+            .map(|_| ())
+            .map_err(SimpleError::DieselError)
+    }
+}
+
+#[derive(Queryable, Debug, Clone)]
+pub struct AuditEntry {
+    pub id: Uuid,
+    pub user: i64,
+    pub user_tag: String,
+    pub guild: Option<i64>,
+    pub channel: i64,
+    pub command: String,
+    pub arguments: String,
+    pub instance: Option<String>,
+    pub permitted: bool,
+    pub error: Option<String>,
+    pub duration_ms: i64,
+    pub created_at: NaiveDateTime,
+}
+
+#[derive(Debug, Clone, Copy)]
+pub enum AuditFilter<'a> {
+    All,
+    User(i64),
+    Instance(&'a str),
+}
+
+/// The latest entries matching the filter, newest first.
+pub fn latest(
+    sql: &mut SqliteConnection,
+    filter: AuditFilter<'_>,
+    limit: i64,
+) -> SimpleResult<Vec<AuditEntry>> {
+    let mut query = dsl::audit_log.into_boxed::<Sqlite>();
+
+    query = match filter {
+        AuditFilter::All => query,
+        AuditFilter::User(user) => query.filter(dsl::user.eq(user)),
+        AuditFilter::Instance(instance) => query.filter(dsl::instance.eq(instance)),
+    };
+
+    query
+        .order(dsl::created_at.desc())
+        .limit(limit)
+        .load(sql)
+        .map_err(Into::into)
+}
@@ -0,0 +1,82 @@
+use super::uuid::Uuid;
+use crate::schema::jobs;
+use crate::schema::jobs::dsl;
+use crate::{SimpleError, SimpleResult};
+use chrono::NaiveDateTime;
+use diesel::prelude::*;
+use diesel::{Insertable, Queryable, SqliteConnection};
+
+#[derive(Insertable, Debug, Clone, Copy)]
+#[diesel(table_name = jobs)]
+pub struct NewJob<'a> {
+    pub id: Uuid,
+    pub server: &'a str,
+    pub kind: &'a str,
+    pub action: Option<&'a str>,
+    pub step: &'a str,
+    pub state: &'a str,
+    pub channel: Option<i64>,
+    pub created_at: NaiveDateTime,
+    pub updated_at: NaiveDateTime,
+    pub user: Option<i64>,
+}
+
+impl NewJob<'_> {
+    pub fn insert(&self, sql: &mut SqliteConnection) -> SimpleResult<()> {
+        diesel::insert_into(dsl::jobs)
+            .values(self)
+            .execute(sql)
+            .map(|_| ())
+            .map_err(SimpleError::DieselError)
+    }
+}
+
+#[derive(Queryable, Debug, Clone)]
+pub struct JobRecord {
+    pub id: Uuid,
+    pub server: String,
+    pub kind: String,
+    pub action: Option<String>,
+    pub step: String,
+    pub state: String,
+    pub channel: Option<i64>,
+    pub created_at: NaiveDateTime,
+    pub updated_at: NaiveDateTime,
+    /// User requesting the job, `None` for jobs started by the bot itself.
+    pub user: Option<i64>,
+}
+
+pub fn set_step(
+    sql: &mut SqliteConnection,
+    id: Uuid,
+    step: &str,
+    now: NaiveDateTime,
+) -> SimpleResult<()> {
+    diesel::update(dsl::jobs.filter(dsl::id.eq(id)))
+        .set((dsl::step.eq(step), dsl::updated_at.eq(now)))
+        .execute(sql)
+        .map(|_| ())
+        .map_err(Into::into)
+}
+
+pub fn set_state(
+    sql: &mut SqliteConnection,
+    id: Uuid,
+    state: &str,
+    now: NaiveDateTime,
+) -> SimpleResult<()> {
+    diesel::update(dsl::jobs.filter(dsl::id.eq(id)))
+        .set((dsl::state.eq(state), dsl::updated_at.eq(now)))
+        .execute(sql)
+        .map(|_| ())
+        .map_err(Into::into)
+}
+
+/// Jobs in the given state, oldest first.
+pub fn in_state(sql: &mut SqliteConnection, state: &str) -> SimpleResult<Vec<JobRecord>> {
+    dsl::jobs
+        .filter(dsl::state.eq(state))
+        .order(dsl::created_at.asc())
+        .load(sql)
+        .map_err(Into::into)
+}
@@ -0,0 +1,51 @@
+use crate::schema::schedule_pauses;
+use crate::schema::schedule_pauses::dsl;
+use crate::{SimpleError, SimpleResult};
+use chrono::NaiveDateTime;
+use diesel::prelude::*;
+use diesel::{Insertable, SqliteConnection};
+
+#[derive(Insertable, Debug, Clone, Copy)]
+#[diesel(table_name = schedule_pauses)]
+pub struct NewSchedulePause<'a> {
+    pub server: &'a str,
+    pub paused_by: i64,
+    pub paused_at: NaiveDateTime,
+}
+
+impl NewSchedulePause<'_> {
+    /// Returns false, if the schedule was already paused.
+    pub fn insert(&self, sql: &mut SqliteConnection) -> SimpleResult<bool> {
+        diesel::insert_into(dsl::schedule_pauses)
+            .values(self)
+            .on_conflict_do_nothing()
+            .execute(sql)
+            .map(|rows| rows > 0)
+            .map_err(SimpleError::DieselError)
+    }
+}
+
+/// Returns false, if the schedule wasn't paused.
+pub fn resume(sql: &mut SqliteConnection, server: &str) -> SimpleResult<bool> {
+    diesel::delete(dsl::schedule_pauses.filter(dsl::server.eq(server)))
+        .execute(sql)
+        .map(|rows| rows > 0)
+        .map_err(Into::into)
+}
+
+pub fn is_paused(sql: &mut SqliteConnection, server: &str) -> SimpleResult<bool> {
+    dsl::schedule_pauses
+        .select(dsl::server)
+        .filter(dsl::server.eq(server))
+        .get_result::<String>(sql)
+        .optional()
+        .map(|s| s.is_some())
+        .map_err(Into::into)
+}
+
+pub fn paused(sql: &mut SqliteConnection) -> SimpleResult<Vec<String>> {
+    dsl::schedule_pauses
+        .select(dsl::server)
+        .load(sql)
+        .map_err(Into::into)
+}
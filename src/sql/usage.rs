@@ -0,0 +1,103 @@
+use super::uuid::Uuid;
+use crate::schema::vm_runs;
+use crate::schema::vm_runs::dsl;
+use crate::{SimpleError, SimpleResult};
+use chrono::NaiveDateTime;
+use diesel::prelude::*;
+use diesel::{Insertable, Queryable, SqliteConnection};
+
+#[derive(Insertable, Debug, Clone, Copy)]
+#[diesel(table_name = vm_runs)]
+pub struct NewVmRun<'a> {
+    pub id: Uuid,
+    pub server: &'a str,
+    pub started_by: Option<i64>,
+    pub started_at: NaiveDateTime,
+}
+
+impl NewVmRun<'_> {
+    /// Returns false, if there is already an open run of the server.
+    pub fn insert(&self, sql: &mut SqliteConnection) -> SimpleResult<bool> {
+        sql.transaction(|sql| {
+            if open_run(sql, self.server)?.is_some() {
+                return Ok(false);
+            }
+
+            diesel::insert_into(dsl::vm_runs)
+                .values(self)
+                .execute(sql)
+                .map(|_| true)
+        })
+        .map_err(SimpleError::DieselError)
+    }
+}
+
+#[derive(Queryable, Debug, Clone)]
+pub struct VmRun {
+    pub id: Uuid,
+    pub server: String,
+    pub started_by: Option<i64>,
+    pub started_at: NaiveDateTime,
+    pub stopped_by: Option<i64>,
+    pub stopped_at: Option<NaiveDateTime>,
+}
+
+fn open_run(sql: &mut SqliteConnection, server: &str) -> QueryResult<Option<VmRun>> {
+    dsl::vm_runs
+        .filter(dsl::server.eq(server))
+        .filter(dsl::stopped_at.is_null())
+        .get_result(sql)
+        .optional()
+}
+
+pub fn is_open(sql: &mut SqliteConnection, server: &str) -> SimpleResult<bool> {
+    open_run(sql, server)
+        .map(|run| run.is_some())
+        .map_err(Into::into)
+}
+
+/// Servers currently running from starts of the user.
+pub fn open_runs_started_by(sql: &mut SqliteConnection, user: i64) -> SimpleResult<Vec<String>> {
+    dsl::vm_runs
+        .select(dsl::server)
+        .filter(dsl::started_by.eq(user))
+        .filter(dsl::stopped_at.is_null())
+        .order(dsl::server.asc())
+        .load(sql)
+        .map_err(Into::into)
+}
+
+/// Returns false, if there was no open run of the server.
+pub fn close(
+    sql: &mut SqliteConnection,
+    server: &str,
+    stopped_by: Option<i64>,
+    stopped_at: NaiveDateTime,
+) -> SimpleResult<bool> {
+    diesel::update(
+        dsl::vm_runs
+            .filter(dsl::server.eq(server))
+            .filter(dsl::stopped_at.is_null()),
+    )
+    .set((
+        dsl::stopped_by.eq(stopped_by),
+        dsl::stopped_at.eq(stopped_at),
+    ))
+    .execute(sql)
+    .map(|rows| rows > 0)
+    .map_err(Into::into)
+}
+
+/// Runs overlapping the given period, open runs included.
+pub fn runs_between(
+    sql: &mut SqliteConnection,
+    from: NaiveDateTime,
+    to: NaiveDateTime,
+) -> SimpleResult<Vec<VmRun>> {
+    dsl::vm_runs
+        .filter(dsl::started_at.lt(to))
+        .filter(dsl::stopped_at.is_null().or(dsl::stopped_at.gt(from)))
+        .order(dsl::started_at.asc())
+        .load(sql)
+        .map_err(Into::into)
+}
@@ -0,0 +1,109 @@
+use crate::azure::fake::{FakeAzure, RESOURCE_GROUP, SUBSCRIPTION, VM};
+use crate::azure::AzureClient;
+use crate::conf::ServerConfig;
+use crate::job::{Job, JobKind};
+use crate::sql::job::in_state;
+use crate::sql::Sql;
+use config::{Config, File, FileFormat};
+use serenity::cache::Cache;
+use serenity::client::bridge::gateway::ShardMessenger;
+use serenity::client::Context;
+use serenity::http::Http;
+use serenity::prelude::{RwLock, TypeMap};
+use std::path::PathBuf;
+use std::sync::Arc;
+use uuid::Uuid;
+
+/// Name of the server on the vm of the fake resource manager.
+pub const S_NAME: &str = "test";
+
+/// Everything a start or stop of the server on the fake resource manager needs.
+pub struct Fixture {
+    pub ctx: Context,
+    pub client: AzureClient,
+    pub sql: Sql,
+    pub server_conf: ServerConfig,
+}
+
+impl Fixture {
+    pub fn new(fake: &FakeAzure) -> Self {
+        Fixture {
+            ctx: context(),
+            client: fake.client(),
+            sql: sql(),
+            server_conf: server_config("echo start", "echo stop"),
+        }
+    }
+
+    pub fn job(&self, kind: JobKind) -> Job {
+        Job::begin(&self.sql, S_NAME, kind, None, None, None).unwrap()
+    }
+
+    /// Number of jobs in the given state.
+    pub fn jobs(&self, state: &str) -> usize {
+        in_state(&mut self.sql.connection.get().unwrap(), state)
+            .unwrap()
+            .len()
+    }
+}
+
+/// Requests of an azure operation, which the fake completes on the second poll.
+pub fn operation(request: &'static str) -> [&'static str; 3] {
+    [request, "GET operation", "GET operation"]
+}
+
+/// Asserts the fake received exactly the given requests, in order.
+pub fn assert_requests(fake: &FakeAzure, expected: &[&[&str]]) {
+    assert_eq!(fake.requests(), expected.concat());
+}
+
+/// Context without a gateway connection, only usable with silent progress messages.
+pub fn context() -> Context {
+    let (tx, _) = futures::channel::mpsc::unbounded();
+
+    Context {
+        data: Arc::new(RwLock::new(TypeMap::new())),
+        shard: ShardMessenger::new(tx),
+        shard_id: 0,
+        http: Arc::new(Http::new("")),
+        cache: Arc::new(Cache::default()),
+    }
+}
+
+/// Migrated in-memory database, which lives as long as the pool.
+pub fn sql() -> Sql {
+    Sql::open(&format!("file:{}?mode=memory&cache=shared", Uuid::new_v4())).unwrap()
+}
+
+/// Server on the vm of the fake resource manager with the given start and stop scripts.
+pub fn server_config(start_script: &str, stop_script: &str) -> ServerConfig {
+    let toml = format!(
+        r#"
+        start_script = {:?}
+        stop_script = {:?}
+
+        [vm]
+        name = "{}"
+        rg = "{}"
+        sub = "{}"
+        "#,
+        script(start_script),
+        script(stop_script),
+        VM,
+        RESOURCE_GROUP,
+        SUBSCRIPTION
+    );
+
+    Config::builder()
+        .add_source(File::from_str(&toml, FileFormat::Toml))
+        .build()
+        .unwrap()
+        .try_deserialize()
+        .unwrap()
+}
+
+fn script(content: &str) -> PathBuf {
+    let path = std::env::temp_dir().join(format!("r6v3-{}.sh", Uuid::new_v4()));
+    std::fs::write(&path, content).unwrap();
+    path
+}